@@ -0,0 +1,172 @@
+//! Replays a telemetry recording through the vibration engine, to tune profiles and engine
+//! parameters against real flights without launching X-Plane.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use xa_ursa_minor_hid::engine::EngineParams;
+use xa_ursa_minor_hid::hid::HIDWrapper;
+use xa_ursa_minor_hid::ipc::{default_endpoint, ensure_device_free};
+use xa_ursa_minor_hid::profile::Profile;
+use xa_ursa_minor_hid::replay::{replay, OutputEvent, DEFAULT_INTERVAL};
+use xa_ursa_minor_hid::telemetry::load_binary;
+
+const USAGE: &str = "\
+Usage: xa-ursa-minor-replay <recording.bin> [options]
+
+Options:
+  --profile <file.json>  Vibration profile to replay with (default: built-in)
+  --params <file.json>   Engine parameters (default: built-in)
+  --set <name>=<value>   Override one engine parameter, e.g. --set max_magnitude=2.0
+  --interval <seconds>   Engine update interval (default: 0.02)
+  --format <csv|json>    Format of the motor timeline (default: csv)
+  --output <file>        Write the timeline to a file instead of stdout
+  --live                 Play the timeline on the connected stick";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+struct Options {
+    recording: PathBuf,
+    profile: Profile,
+    params: EngineParams,
+    interval: f64,
+    format: Format,
+    output: Option<PathBuf>,
+    live: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut recording = None;
+    let mut profile = Profile::default();
+    let mut params = EngineParams::default();
+    let mut overrides = Vec::new();
+    let mut interval = DEFAULT_INTERVAL;
+    let mut format = Format::Csv;
+    let mut output = None;
+    let mut live = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--profile" => profile = Profile::load(value()?.as_ref())?,
+            "--params" => params = EngineParams::load(value()?.as_ref())?,
+            "--set" => overrides.push(value()?),
+            "--interval" => {
+                interval = value()?
+                    .parse()
+                    .map_err(|e| format!("Invalid interval: {e}"))?;
+                // NaN would slip past a plain `<= 0.0` and stall the replay loop.
+                if !(interval.is_finite() && interval > 0.0) {
+                    return Err("The interval must be a positive number".to_string());
+                }
+            }
+            "--format" => {
+                format = match value()?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    other => return Err(format!("Unknown format {other}")),
+                }
+            }
+            "--output" => output = Some(PathBuf::from(value()?)),
+            "--live" => live = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {arg}\n\n{USAGE}")),
+            _ if recording.is_none() => recording = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument {arg}\n\n{USAGE}")),
+        }
+    }
+
+    // Overrides apply on top of the parameter file, whatever the order on the command line.
+    for assignment in overrides {
+        params.assign(&assignment)?;
+    }
+    Ok(Options {
+        recording: recording.ok_or(USAGE)?,
+        profile,
+        params,
+        interval,
+        format,
+        output,
+        live,
+    })
+}
+
+fn format_timeline(timeline: &[OutputEvent], format: Format) -> Result<String, String> {
+    match format {
+        Format::Csv => {
+            let mut csv = String::from("time,intensity\n");
+            for event in timeline {
+                csv.push_str(&format!("{},{}\n", event.time, event.intensity));
+            }
+            Ok(csv)
+        }
+        Format::Json => serde_json::to_string_pretty(timeline)
+            .map_err(|e| format!("Failed to serialize the timeline: {e}")),
+    }
+}
+
+/// Write the timeline to the stick in real time, then stop the motor.
+fn play_live(timeline: &[OutputEvent]) -> Result<(), String> {
+    ensure_device_free(&default_endpoint())?;
+    let mut hid = HIDWrapper::new().ok_or("Could not open the HID device")?;
+    let started = Instant::now();
+    for event in timeline {
+        let due = started + Duration::from_secs_f64(event.time.max(0.0));
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        hid.write_vibration(event.intensity)?;
+    }
+    hid.write_vibration(0)
+}
+
+fn run() -> Result<(), String> {
+    let options = parse_args(std::env::args().skip(1))?;
+    let records = load_binary(&options.recording)?;
+    let timeline = replay(&records, options.profile, options.params, options.interval);
+
+    let formatted = format_timeline(&timeline, options.format)?;
+    match &options.output {
+        Some(path) => fs::write(path, formatted)
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?,
+        None if !options.live => print!("{formatted}"),
+        None => {}
+    }
+
+    if options.live {
+        play_live(&timeline)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_interval_must_be_positive_and_finite() {
+        for interval in ["0", "-0.02", "NaN", "inf"] {
+            assert!(
+                parse(&["flight.bin", "--interval", interval]).is_err(),
+                "{interval} was accepted"
+            );
+        }
+        let options = parse(&["flight.bin", "--interval", "0.05"]).unwrap();
+        assert_eq!(options.interval, 0.05);
+    }
+}
@@ -0,0 +1,378 @@
+//! Drives the stick from X-Plane's UDP output, for X-Plane running on another machine than the
+//! stick (or without the plugin). Can also stand in for X-Plane by replaying captured packets,
+//! and take haptic messages from any other application (see `xa_ursa_minor_hid::external`).
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use xa_ursa_minor_hid::engine::{EngineParams, VibrationEngine};
+use xa_ursa_minor_hid::external::{self, ExternalInput, ExternalMessage};
+use xa_ursa_minor_hid::hid::HIDWrapper;
+use xa_ursa_minor_hid::ipc::{default_endpoint, ensure_device_free};
+use xa_ursa_minor_hid::pattern::Pattern;
+use xa_ursa_minor_hid::profile::Profile;
+use xa_ursa_minor_hid::udp::{
+    parse_packet, read_capture, serve_capture, subscribe, CaptureWriter, UdpSampler, XPLANE_PORT,
+};
+
+const USAGE: &str = "\
+Usage: xa-ursa-minor-udp [options]
+       xa-ursa-minor-udp --stand-in <capture> [--target <host:port>]
+
+Options:
+  --xplane <host[:port]>  X-Plane to subscribe to (default: 127.0.0.1:49000)
+  --listen <addr:port>    Where packets are received (default: 0.0.0.0:49005)
+  --rate <per second>     How often X-Plane sends the datarefs (default: 30)
+  --no-subscribe          Don't request datarefs; use the DATA output set up in X-Plane
+  --profile <file.json>   Vibration profile (default: built-in)
+  --params <file.json>    Engine parameters (default: built-in)
+  --set <name>=<value>    Override one engine parameter, e.g. --set max_magnitude=2.0
+  --capture <file>        Save the received packets, to replay them with --stand-in
+  --external [addr:port]  Also take haptic messages from other applications
+                          (default: 127.0.0.1:49010). Their g-force is ignored
+                          while X-Plane sends data
+  --patterns <dir>        Patterns that haptic effect messages can play by name
+
+Stand-in:
+  --stand-in <capture>    Send captured packets with their original timing instead of X-Plane
+  --target <host:port>    Where the stand-in sends them (default: 127.0.0.1:49005)";
+
+/// How often the engine is updated and the motor written, like the plugin's worker.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Subscribe again after this long without packets, e.g. after X-Plane was restarted.
+const RESUBSCRIBE_AFTER: Duration = Duration::from_secs(5);
+
+/// External g-force messages are ignored until X-Plane has sent no sample for this long.
+const SOURCE_HANDOVER: Duration = Duration::from_secs(1);
+
+/// Where the g-force samples come from. X-Plane's are stamped with sim time and the external
+/// ones with receive time, so only one source feeds the engine at a time, X-Plane first.
+#[derive(Clone, Copy, PartialEq)]
+enum GForceSource {
+    XPlane,
+    External,
+}
+
+/// Switch the engine to samples from `source`. The previous source's samples can't be compared
+/// with the new ones, so the engine starts over.
+fn use_source(
+    engine: &mut VibrationEngine,
+    current: &mut Option<GForceSource>,
+    source: GForceSource,
+) {
+    if current
+        .replace(source)
+        .is_some_and(|previous| previous != source)
+    {
+        engine.reset_input();
+    }
+}
+
+/// Set by Ctrl-C or a termination request; the client then zeroes the motor and exits.
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn install_termination_handler() -> Result<(), String> {
+    extern "C" fn on_signal(_: libc::c_int) {
+        TERMINATE.store(true, Ordering::Relaxed);
+    }
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // Storing to an atomic is async-signal-safe.
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(format!("Failed to install a handler for signal {signal}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn install_termination_handler() -> Result<(), String> {
+    type HandlerRoutine = unsafe extern "system" fn(u32) -> i32;
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<HandlerRoutine>, add: i32) -> i32;
+    }
+    /// Ctrl-C, Ctrl-Break, closing the console, logoff and shutdown all come through here.
+    unsafe extern "system" fn on_ctrl(_: u32) -> i32 {
+        TERMINATE.store(true, Ordering::Relaxed);
+        // Give the client a moment to zero the motor before Windows ends the process.
+        std::thread::sleep(UPDATE_INTERVAL * 5);
+        1
+    }
+    if unsafe { SetConsoleCtrlHandler(Some(on_ctrl), 1) } == 0 {
+        return Err("Failed to install a Ctrl-C handler".to_string());
+    }
+    Ok(())
+}
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:49005";
+const DEFAULT_TARGET: &str = "127.0.0.1:49005";
+
+struct ClientOptions {
+    xplane: SocketAddr,
+    listen: SocketAddr,
+    rate: i32,
+    subscribe: bool,
+    profile: Profile,
+    params: EngineParams,
+    capture: Option<PathBuf>,
+    external: Option<SocketAddr>,
+    patterns: Vec<Pattern>,
+}
+
+enum Mode {
+    Client(Box<ClientOptions>),
+    StandIn {
+        capture: PathBuf,
+        target: SocketAddr,
+    },
+}
+
+fn resolve(address: &str, default_port: u16) -> Result<SocketAddr, String> {
+    let with_port = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{address}:{default_port}")
+    };
+    with_port
+        .to_socket_addrs()
+        .map_err(|e| format!("Invalid address {address}: {e}"))?
+        .next()
+        .ok_or(format!("Invalid address {address}"))
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Mode, String> {
+    let mut xplane = resolve("127.0.0.1", XPLANE_PORT)?;
+    let mut listen = resolve(DEFAULT_LISTEN, 0)?;
+    let mut rate = 30;
+    let mut subscribe = true;
+    let mut profile = Profile::default();
+    let mut params = EngineParams::default();
+    let mut overrides = Vec::new();
+    let mut capture = None;
+    let mut external = None;
+    let mut patterns = Vec::new();
+    let mut stand_in = None;
+    let mut target = resolve(DEFAULT_TARGET, 0)?;
+
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--xplane" => xplane = resolve(&value()?, XPLANE_PORT)?,
+            "--listen" => listen = resolve(&value()?, 0)?,
+            "--rate" => {
+                rate = value()?.parse().map_err(|e| format!("Invalid rate: {e}"))?;
+                if rate <= 0 {
+                    return Err("The rate must be positive".to_string());
+                }
+            }
+            "--no-subscribe" => subscribe = false,
+            "--profile" => profile = Profile::load(value()?.as_ref())?,
+            "--params" => params = EngineParams::load(value()?.as_ref())?,
+            "--set" => overrides.push(value()?),
+            "--capture" => capture = Some(PathBuf::from(value()?)),
+            "--external" => {
+                // The address is optional; take the next argument only if it isn't an option.
+                let address = match args.next_if(|next| !next.starts_with("--")) {
+                    Some(address) => resolve(&address, external::DEFAULT_PORT)?,
+                    None => resolve("127.0.0.1", external::DEFAULT_PORT)?,
+                };
+                external = Some(address);
+            }
+            "--patterns" => patterns = load_patterns(value()?.as_ref())?,
+            "--stand-in" => stand_in = Some(PathBuf::from(value()?)),
+            "--target" => target = resolve(&value()?, 0)?,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("Unknown argument {arg}\n\n{USAGE}")),
+        }
+    }
+
+    if let Some(capture) = stand_in {
+        return Ok(Mode::StandIn { capture, target });
+    }
+    for assignment in overrides {
+        params.assign(&assignment)?;
+    }
+    Ok(Mode::Client(Box::new(ClientOptions {
+        xplane,
+        listen,
+        rate,
+        subscribe,
+        profile,
+        params,
+        capture,
+        external,
+        patterns,
+    })))
+}
+
+/// Every pattern in `dir`; a pattern that fails to load is an error, unlike in the plugin,
+/// since it's easy to fix and run again here.
+fn load_patterns(dir: &Path) -> Result<Vec<Pattern>, String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {e}", dir.display()))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| Pattern::load(path).map_err(|e| format!("{}: {e}", path.display())))
+        .collect()
+}
+
+/// Receive packets and drive the stick until terminated or an unrecoverable error. Either way
+/// the motor is left off and X-Plane stops sending.
+fn run_client(options: ClientOptions) -> Result<(), String> {
+    install_termination_handler()?;
+    let socket = UdpSocket::bind(options.listen)
+        .map_err(|e| format!("Failed to listen on {}: {e}", options.listen))?;
+    ensure_device_free(&default_endpoint())?;
+    let mut hid = HIDWrapper::new().ok_or("Could not open the HID device")?;
+    let mut capture = options
+        .capture
+        .as_deref()
+        .map(CaptureWriter::create)
+        .transpose()?;
+    let mut engine = VibrationEngine::new(options.profile, options.params);
+    let mut sampler = UdpSampler::new();
+    println!("Listening on {}", options.listen);
+    let external = options.external.map(external::listen).transpose()?;
+    if let Some(address) = options.external {
+        println!("Listening for haptic messages on {address}");
+    }
+    let mut external_input = ExternalInput::new(options.patterns);
+
+    let started = Instant::now();
+    let seconds = |instant: Instant| instant.duration_since(started).as_secs_f64();
+    let mut next_update = started;
+    let mut last_packet: Option<Instant> = None;
+    let mut last_xplane_sample: Option<Instant> = None;
+    let mut source = None;
+    let mut last_subscribed: Option<Instant> = None;
+    let mut last_intensity = 0;
+    let mut buf = [0u8; 4096];
+    let mut receive = || -> Result<(), String> {
+        while !TERMINATE.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            if options.subscribe {
+                let quiet = last_packet.is_none_or(|last| now - last > RESUBSCRIBE_AFTER);
+                let due = last_subscribed.is_none_or(|last| now - last > RESUBSCRIBE_AFTER);
+                if quiet && due {
+                    subscribe(&socket, options.xplane, options.rate)?;
+                    println!("Requested datarefs from X-Plane at {}", options.xplane);
+                    last_subscribed = Some(now);
+                }
+            }
+
+            if now >= next_update {
+                // Messages from other applications are taken in once per update.
+                for message in external.iter().flat_map(|rx| rx.try_iter()) {
+                    if matches!(message, ExternalMessage::GForce { .. }) {
+                        if last_xplane_sample.is_some_and(|last| now - last < SOURCE_HANDOVER) {
+                            continue;
+                        }
+                        use_source(&mut engine, &mut source, GForceSource::External);
+                    }
+                    if let Err(e) = external_input.apply(&mut engine, message, seconds(now)) {
+                        eprintln!("Ignoring haptic message: {e}");
+                    }
+                }
+                let intensity = engine.update(seconds(now));
+                if intensity != last_intensity {
+                    if let Err(e) = hid.write_vibration(intensity) {
+                        eprintln!("Failed to write vibration to device: {e}");
+                    }
+                    last_intensity = intensity;
+                }
+                next_update += UPDATE_INTERVAL;
+                // Don't try to catch up after a stall.
+                if next_update < now {
+                    next_update = now + UPDATE_INTERVAL;
+                }
+                continue;
+            }
+
+            socket
+                .set_read_timeout(Some(next_update - now))
+                .map_err(|e| format!("Failed to set the receive timeout: {e}"))?;
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                // Windows reports an earlier send that nobody received (X-Plane not running yet)
+                // as a reset on the next receive; signals interrupt it on Unix.
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock
+                            | ErrorKind::TimedOut
+                            | ErrorKind::ConnectionReset
+                            | ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(format!("Failed to receive: {e}")),
+            };
+            let received = Instant::now();
+            last_packet = Some(received);
+            if let Some(capture) = &mut capture {
+                capture.write(&buf[..len])?;
+            }
+            match parse_packet(&buf[..len]) {
+                Ok(packet) => {
+                    if let Some(sample) = sampler.receive(&packet, seconds(received)) {
+                        use_source(&mut engine, &mut source, GForceSource::XPlane);
+                        last_xplane_sample = Some(received);
+                        engine.push_sample(&sample, seconds(received));
+                    }
+                }
+                Err(e) => eprintln!("Ignoring packet: {e}"),
+            }
+        }
+        Ok(())
+    };
+    let result = receive();
+
+    if let Err(e) = hid.write_vibration(0) {
+        eprintln!("Failed to stop the motor: {e}");
+    }
+    if options.subscribe {
+        if let Err(e) = subscribe(&socket, options.xplane, 0) {
+            eprintln!("{e}");
+        }
+    }
+    if result.is_ok() {
+        println!("Stopped");
+    }
+    result
+}
+
+fn run_stand_in(capture: PathBuf, target: SocketAddr) -> Result<(), String> {
+    let file = std::fs::File::open(&capture)
+        .map_err(|e| format!("Failed to open {}: {e}", capture.display()))?;
+    let packets = read_capture(file)?;
+    let socket =
+        UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to open a socket: {e}"))?;
+    println!("Sending {} packets to {target}", packets.len());
+    serve_capture(&packets, &socket, target)
+}
+
+fn run() -> Result<(), String> {
+    match parse_args(std::env::args().skip(1))? {
+        Mode::Client(options) => run_client(*options),
+        Mode::StandIn { capture, target } => run_stand_in(capture, target),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,1157 @@
+use crate::filter::Filter3D;
+use crate::pattern::Pattern;
+use crate::profile::{EffectProfile, InputMode, Profile};
+use crate::sample::SimSample;
+use crate::validate::check_positive;
+use crate::waveform::Waveform;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Frame period the g-force deltas are normalized to. Deltas are scaled by
+/// `REFERENCE_DT / dt`, so `max_magnitude` and the sensitivities mean the same at any frame rate
+/// or flight loop interval as they did when the flight loop ran every frame at 60 FPS.
+pub const REFERENCE_DT: f32 = 1.0 / 60.0;
+
+/// Shortest time between samples that is taken at face value when it comes from the receive
+/// time rather than sim time. Datagrams the network delivers in a burst would otherwise make a
+/// near-zero `dt` and blow the normalized deltas up.
+pub const MIN_SAMPLE_DT: f32 = REFERENCE_DT / 2.0;
+
+/// Seconds the output takes to fade out when the sim pauses, enters a replay or a menu, and to
+/// fade back in when it resumes.
+pub const SUPPRESS_FADE: f32 = 0.3;
+
+/// Layer fed by the g-force waves.
+pub const LAYER_GFORCE: &str = "gforce";
+/// Layer fed by the haptic pattern player.
+pub const LAYER_PATTERN: &str = "pattern";
+/// Layer fed by third-party aircraft through the `override_intensity` dataref.
+pub const LAYER_EXTERNAL: &str = "external";
+/// Layer fed by the stick shaker.
+pub const LAYER_SHAKER: &str = "shaker";
+
+/// (side, axial, normal) values.
+pub type Axes = (f32, f32, f32);
+
+/// Numeric parameters of the engine. Missing fields in a parameter file fall back to the
+/// defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineParams {
+    /// Filtered input magnitude mapped to full intensity.
+    pub max_magnitude: f32,
+    /// Composed outputs below this are written as 0.
+    pub min_motor_intensity: u8,
+    /// Wave frequency for a steady input.
+    pub base_frequency: f32,
+    /// How much a change of magnitude raises the wave frequency.
+    pub frequency_sensitivity: f32,
+    /// Wave sharpness (shaping exponent) for a steady input.
+    pub base_sharpness: f32,
+    /// How much a change of magnitude sharpens the wave.
+    pub sharpness_sensitivity: f32,
+    /// Stick shaker intensity during the "on" part of each pulse.
+    pub shaker_intensity: u8,
+    /// Stick shaker intensity between pulses. Kept above zero so the shake feels sustained.
+    pub shaker_low_intensity: u8,
+    /// Most waves a layer keeps at once. Further waves replace the weakest one, so an update
+    /// costs the same at any frame rate.
+    pub max_waves: usize,
+    /// Seconds within which a new wave is merged into the previous one instead of being added.
+    pub merge_window: f32,
+    /// How a wave is merged into one that's already running.
+    pub wave_merge: WaveMerge,
+}
+
+/// How a new wave is merged into the previous one when they start within the merge window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaveMerge {
+    /// Keep whichever of the two waves is stronger.
+    ReplaceWeakest,
+    /// Keep the running wave's timing and shape, raised to the higher of the two peaks. The new
+    /// wave's tail past the running wave's end is dropped rather than stretching the shape.
+    #[default]
+    ExtendExisting,
+    /// Like `ExtendExisting`, but the peaks add up (clamped to 255). Each peak counts in
+    /// proportion to the time its sample covers, so the sum doesn't grow with the frame rate.
+    Sum,
+}
+
+impl WaveMerge {
+    pub const ALL: [WaveMerge; 3] = [
+        WaveMerge::ReplaceWeakest,
+        WaveMerge::ExtendExisting,
+        WaveMerge::Sum,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WaveMerge::ReplaceWeakest => "replace_weakest",
+            WaveMerge::ExtendExisting => "extend_existing",
+            WaveMerge::Sum => "sum",
+        }
+    }
+}
+
+impl Default for EngineParams {
+    fn default() -> Self {
+        Self {
+            max_magnitude: 1.5,
+            min_motor_intensity: 3,
+            base_frequency: 1.0,
+            frequency_sensitivity: 2.0,
+            base_sharpness: 1.0,
+            sharpness_sensitivity: 2.0,
+            shaker_intensity: 255,
+            shaker_low_intensity: 140,
+            max_waves: 8,
+            merge_window: 0.025,
+            wave_merge: WaveMerge::default(),
+        }
+    }
+}
+
+impl EngineParams {
+    /// Load parameters from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let params: Self = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid engine parameters in {}: {e}", path.display()))?;
+        params
+            .validate()
+            .map_err(|e| format!("Invalid engine parameters in {}: {e}", path.display()))?;
+        Ok(params)
+    }
+
+    /// Check the values a parameter file can set outside the tunable ranges.
+    pub fn validate(&self) -> Result<(), String> {
+        // Divisors and wave timings: zero, negative or NaN values would silence or stall waves.
+        for (name, value) in [
+            ("max_magnitude", self.max_magnitude),
+            ("base_frequency", self.base_frequency),
+            ("base_sharpness", self.base_sharpness),
+        ] {
+            check_positive(name, value)?;
+        }
+        for (name, value) in [
+            ("frequency_sensitivity", self.frequency_sensitivity),
+            ("sharpness_sensitivity", self.sharpness_sensitivity),
+            ("merge_window", self.merge_window),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(format!("{name} must be zero or more, got {value}"));
+            }
+        }
+        // Each update walks every wave, so the cap keeps its cost bounded.
+        let (min_waves, max_waves, _) = Tunable::MaxWaves.range();
+        if !(min_waves as usize..=max_waves as usize).contains(&self.max_waves) {
+            return Err(format!(
+                "max_waves must be between {min_waves} and {max_waves}, got {}",
+                self.max_waves
+            ));
+        }
+        Ok(())
+    }
+
+    /// Apply a `<name>=<value>` assignment of a tunable, e.g. from the command line.
+    pub fn assign(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or(format!("Expected <name>=<value>, got {assignment}"))?;
+        let tunable = Tunable::from_name(name).ok_or_else(|| {
+            let names: Vec<_> = Tunable::ALL.iter().map(|t| t.name()).collect();
+            format!(
+                "Unknown parameter {name}, expected one of {}",
+                names.join(", ")
+            )
+        })?;
+        let value: f32 = value
+            .parse()
+            .map_err(|e| format!("Invalid value for {name}: {e}"))?;
+        // Clamping lets NaN through, and would turn it into 0 for the integer parameters.
+        if !value.is_finite() {
+            return Err(format!("Invalid value for {name}: {value}"));
+        }
+        let mut params = self.clone();
+        tunable.set(&mut params, value);
+        params.validate()?;
+        *self = params;
+        Ok(())
+    }
+}
+
+/// Engine parameters that can be tuned live (settings window) or from the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tunable {
+    MaxMagnitude,
+    MinMotorIntensity,
+    BaseFrequency,
+    FrequencySensitivity,
+    BaseSharpness,
+    SharpnessSensitivity,
+    MaxWaves,
+    MergeWindow,
+    /// The index of the policy in `WaveMerge::ALL`.
+    WaveMerge,
+}
+
+impl Tunable {
+    pub const ALL: [Tunable; 9] = [
+        Tunable::MaxMagnitude,
+        Tunable::MinMotorIntensity,
+        Tunable::BaseFrequency,
+        Tunable::FrequencySensitivity,
+        Tunable::BaseSharpness,
+        Tunable::SharpnessSensitivity,
+        Tunable::MaxWaves,
+        Tunable::MergeWindow,
+        Tunable::WaveMerge,
+    ];
+
+    /// Name of the matching `EngineParams` field.
+    pub fn name(self) -> &'static str {
+        match self {
+            Tunable::MaxMagnitude => "max_magnitude",
+            Tunable::MinMotorIntensity => "min_motor_intensity",
+            Tunable::BaseFrequency => "base_frequency",
+            Tunable::FrequencySensitivity => "frequency_sensitivity",
+            Tunable::BaseSharpness => "base_sharpness",
+            Tunable::SharpnessSensitivity => "sharpness_sensitivity",
+            Tunable::MaxWaves => "max_waves",
+            Tunable::MergeWindow => "merge_window",
+            Tunable::WaveMerge => "wave_merge",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|tunable| tunable.name() == name)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Tunable::MaxMagnitude => "Full scale (g)",
+            Tunable::MinMotorIntensity => "Min intensity",
+            Tunable::BaseFrequency => "Base frequency",
+            Tunable::FrequencySensitivity => "Freq. sensitivity",
+            Tunable::BaseSharpness => "Base sharpness",
+            Tunable::SharpnessSensitivity => "Sharp. sensitivity",
+            Tunable::MaxWaves => "Max waves",
+            Tunable::MergeWindow => "Merge window (s)",
+            Tunable::WaveMerge => "Wave merge",
+        }
+    }
+
+    /// Allowed `(min, max, step)` of the value.
+    pub fn range(self) -> (f32, f32, f32) {
+        match self {
+            Tunable::MaxMagnitude => (0.1, 5.0, 0.05),
+            Tunable::MinMotorIntensity => (0.0, 50.0, 1.0),
+            Tunable::BaseFrequency => (0.1, 10.0, 0.1),
+            Tunable::FrequencySensitivity => (0.0, 10.0, 0.1),
+            Tunable::BaseSharpness => (1.0, 5.0, 0.1),
+            Tunable::SharpnessSensitivity => (0.0, 10.0, 0.1),
+            Tunable::MaxWaves => (1.0, 32.0, 1.0),
+            Tunable::MergeWindow => (0.0, 0.1, 0.005),
+            Tunable::WaveMerge => (0.0, (WaveMerge::ALL.len() - 1) as f32, 1.0),
+        }
+    }
+
+    pub fn get(self, params: &EngineParams) -> f32 {
+        match self {
+            Tunable::MaxMagnitude => params.max_magnitude,
+            Tunable::MinMotorIntensity => params.min_motor_intensity as f32,
+            Tunable::BaseFrequency => params.base_frequency,
+            Tunable::FrequencySensitivity => params.frequency_sensitivity,
+            Tunable::BaseSharpness => params.base_sharpness,
+            Tunable::SharpnessSensitivity => params.sharpness_sensitivity,
+            Tunable::MaxWaves => params.max_waves as f32,
+            Tunable::MergeWindow => params.merge_window,
+            Tunable::WaveMerge => WaveMerge::ALL
+                .iter()
+                .position(|&merge| merge == params.wave_merge)
+                .unwrap_or(0) as f32,
+        }
+    }
+
+    /// Set the value, clamped to the allowed range.
+    pub fn set(self, params: &mut EngineParams, value: f32) {
+        let (min, max, _) = self.range();
+        let value = value.clamp(min, max);
+        match self {
+            Tunable::MaxMagnitude => params.max_magnitude = value,
+            Tunable::MinMotorIntensity => params.min_motor_intensity = value.round() as u8,
+            Tunable::BaseFrequency => params.base_frequency = value,
+            Tunable::FrequencySensitivity => params.frequency_sensitivity = value,
+            Tunable::BaseSharpness => params.base_sharpness = value,
+            Tunable::SharpnessSensitivity => params.sharpness_sensitivity = value,
+            Tunable::MaxWaves => params.max_waves = value.round() as usize,
+            Tunable::MergeWindow => params.merge_window = value,
+            Tunable::WaveMerge => params.wave_merge = WaveMerge::ALL[value.round() as usize],
+        }
+    }
+}
+
+/// A single “wave event” that starts at `start_time`, has a peak amplitude
+/// (`target_intensity`), and lasts for `duration` seconds.
+struct WaveEvent {
+    start_time: f64,
+    duration: f32,
+    waveform: Waveform,
+    target_intensity: u8,
+    wave_frequency: f32, // per-wave frequency
+    wave_sharpness: f32, // per-wave shaping exponent
+    seed: u32,           // picks the noise sequence for noise bursts
+    /// Share (0..1) of a reference frame the spawning sample covers, which its peak counts for
+    /// when `WaveMerge::Sum` adds peaks up. 1 once the wave holds a sum.
+    weight: f32,
+}
+
+impl WaveEvent {
+    /// Return the intensity of this wave at engine time `now`.
+    /// If the wave has expired, return `None`.
+    fn current_intensity(&self, now: f64) -> Option<u8> {
+        let elapsed = (now - self.start_time).max(0.0) as f32;
+        if elapsed > self.duration {
+            // Wave is fully expired
+            return None;
+        }
+
+        // 0..1 progress through the wave
+        let progress = elapsed / self.duration;
+
+        let shaped = self.waveform.sample(
+            progress,
+            self.wave_frequency,
+            self.wave_sharpness,
+            self.seed,
+        );
+
+        let intensity_f = shaped * (self.target_intensity as f32);
+        let intensity = intensity_f.round().clamp(0.0, 255.0) as u8;
+
+        Some(intensity)
+    }
+
+    /// Fold `new` into this wave.
+    fn merge(&mut self, new: WaveEvent, merge: WaveMerge) {
+        match merge {
+            WaveMerge::ReplaceWeakest => {
+                if new.target_intensity > self.target_intensity {
+                    *self = new;
+                }
+            }
+            WaveMerge::ExtendExisting => {
+                self.target_intensity = self.target_intensity.max(new.target_intensity);
+            }
+            WaveMerge::Sum => {
+                let sum = self.target_intensity as f32 * self.weight
+                    + new.target_intensity as f32 * new.weight;
+                self.target_intensity = sum.round().clamp(0.0, 255.0) as u8;
+                self.weight = 1.0;
+            }
+        }
+    }
+}
+
+/// Stick shaker emulation. While active it overrides every wave with a sustained pulse train.
+struct StickShaker {
+    active_since: Option<f64>,
+}
+
+impl StickShaker {
+    fn new() -> Self {
+        Self { active_since: None }
+    }
+
+    fn set_active(&mut self, active: bool, now: f64) {
+        match (active, self.active_since) {
+            (true, None) => self.active_since = Some(now),
+            (false, Some(_)) => self.active_since = None,
+            _ => {}
+        }
+    }
+
+    /// Return the shaker intensity at `now`, or `None` if the shaker is off.
+    /// Each pulse follows the shaker effect's waveform, scaled between the low and high intensity.
+    fn current_intensity(
+        &self,
+        now: f64,
+        effect: &EffectProfile,
+        params: &EngineParams,
+    ) -> Option<u8> {
+        let started = self.active_since?;
+        let elapsed = (now - started).max(0.0) as f32;
+
+        // Position (0..1) inside the current pulse
+        let pulses = elapsed / effect.duration;
+        let level = effect
+            .waveform
+            .sample(pulses.fract(), 1.0, 1.0, pulses as u32);
+
+        let low = params.shaker_low_intensity as f32;
+        let high = params.shaker_intensity as f32;
+        Some((low + (high - low) * level).round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+/// How a layer's intensity is combined with the (lower priority) layers below it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    /// Pointwise max with the layers below.
+    Max,
+    /// Add to the layers below, clamped to 255.
+    SumClamp,
+    /// Replace the layers below while this layer is active.
+    Override,
+    /// While active, scale the layers below by the given factor (0..1), then take the max.
+    Duck(f32),
+}
+
+impl BlendMode {
+    fn blend(self, below: u8, level: u8) -> u8 {
+        match self {
+            BlendMode::Max => below.max(level),
+            BlendMode::SumClamp => below.saturating_add(level),
+            BlendMode::Override => level,
+            BlendMode::Duck(factor) => {
+                let ducked = (below as f32 * factor.clamp(0.0, 1.0)).round() as u8;
+                ducked.max(level)
+            }
+        }
+    }
+}
+
+/// A named effect source with its own waves. Layers are composed in priority order.
+struct EffectLayer {
+    name: &'static str,
+    priority: i32,
+    gain: f32,
+    blend: BlendMode,
+    /// Active waves of this layer, removed once they're expired.
+    waves: Vec<WaveEvent>,
+    /// Constant level held until cleared (e.g. the stick shaker), merged with the waves by max.
+    hold: Option<u8>,
+    /// Intensity computed on the last update, after gain. Kept for introspection.
+    intensity: u8,
+}
+
+impl EffectLayer {
+    fn new(name: &'static str, priority: i32, gain: f32, blend: BlendMode) -> Self {
+        Self {
+            name,
+            priority,
+            gain,
+            blend,
+            waves: Vec::new(),
+            hold: None,
+            intensity: 0,
+        }
+    }
+
+    /// Add a wave, merging it into the latest one when it starts within the merge window. When the
+    /// layer already holds `max_waves`, the new wave replaces the weakest one if it's at least as
+    /// strong: merging would tie it to a running wave's timing, which may be about to end.
+    fn add_wave(&mut self, wave: WaveEvent, params: &EngineParams) {
+        let latest = self
+            .waves
+            .iter_mut()
+            .max_by(|a, b| a.start_time.total_cmp(&b.start_time))
+            .filter(|latest| wave.start_time - latest.start_time <= params.merge_window as f64);
+        if let Some(latest) = latest {
+            latest.merge(wave, params.wave_merge);
+        } else if self.waves.len() < params.max_waves.max(1) {
+            self.waves.push(wave);
+        } else {
+            let weakest = self
+                .waves
+                .iter_mut()
+                .min_by_key(|wave| wave.target_intensity);
+            if let Some(weakest) =
+                weakest.filter(|weakest| weakest.target_intensity <= wave.target_intensity)
+            {
+                *weakest = wave;
+            }
+        }
+    }
+
+    /// Drop expired waves and return this layer's intensity at `now`, after gain.
+    fn update(&mut self, now: f64) -> u8 {
+        // Compute the maximum intensity across all active waves
+        let mut max_intensity = self.hold.unwrap_or(0);
+        self.waves.retain(|wave| {
+            if let Some(current) = wave.current_intensity(now) {
+                if current > max_intensity {
+                    max_intensity = current;
+                }
+                true // wave is still active
+            } else {
+                false // wave has expired
+            }
+        });
+
+        self.intensity = (max_intensity as f32 * self.gain).round().clamp(0.0, 255.0) as u8;
+        self.intensity
+    }
+}
+
+/// Snapshot of a layer's state, for debugging.
+#[derive(Clone, Debug)]
+pub struct LayerStatus {
+    pub name: &'static str,
+    pub priority: i32,
+    pub gain: f32,
+    pub blend: BlendMode,
+    pub intensity: u8,
+    pub active_waves: usize,
+}
+
+/// What the engine derived from one sample, for telemetry.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessedSample {
+    /// Normalized g-force change since the previous sample.
+    pub delta: Option<Axes>,
+    /// Weighted and filtered input of the g-force effect.
+    pub filtered: Option<Axes>,
+}
+
+/// The vibration engine keeps named effect layers, each with its own waves. Every update the
+/// layers are composed from lowest to highest priority using each layer's blend mode.
+/// By default the g-force waves are merged by pointwise max, and the stick shaker sits on top of
+/// them as an override layer.
+///
+/// The engine doesn't know about devices or wall-clock time: every call takes the engine time in
+/// seconds, so the same code runs live in the plugin and faster than real time in a replay.
+pub struct VibrationEngine {
+    /// Effect layers, sorted by ascending priority.
+    layers: Vec<EffectLayer>,
+
+    /// When false, the output is kept at zero.
+    enabled: bool,
+
+    /// Master gain applied to the composed output of all layers.
+    gain: f32,
+
+    /// The sim is paused, in a replay or in a menu: no waves are spawned and the output fades
+    /// out.
+    suppressed: bool,
+
+    /// Output level (0..1) ramping between 0 while suppressed and 1 otherwise.
+    fade: f32,
+
+    /// When `update` last ran, to advance the fade.
+    last_update: Option<f64>,
+
+    /// Per-axis filters from the profile, applied to the weighted input.
+    filter: Filter3D,
+
+    previous_mag: f32,
+
+    /// Previous sample, to derive g-force deltas from.
+    last_sample: Option<SimSample>,
+
+    /// Previous normalized g-force delta, to derive jerk from.
+    last_delta: Option<Axes>,
+
+    /// Drives the hold level of the shaker layer.
+    shaker: StickShaker,
+
+    /// Pattern currently playing on the pattern layer, and when it started.
+    pattern: Option<(Pattern, f64)>,
+
+    /// Waveform and duration for each effect.
+    profile: Profile,
+
+    params: EngineParams,
+
+    /// Incremented per wave so every noise burst is different.
+    wave_seed: u32,
+}
+
+impl VibrationEngine {
+    /// Create a new engine with the default layers and no active waves.
+    pub fn new(profile: Profile, params: EngineParams) -> Self {
+        let mut engine = Self {
+            layers: Vec::new(),
+            enabled: true,
+            gain: 1.0,
+            suppressed: false,
+            fade: 1.0,
+            last_update: None,
+            filter: Filter3D::new(&profile.filter),
+            previous_mag: 0.0,
+            last_sample: None,
+            last_delta: None,
+            shaker: StickShaker::new(),
+            pattern: None,
+            profile,
+            params,
+            wave_seed: 0,
+        };
+        engine.add_layer(LAYER_GFORCE, 0, 1.0, BlendMode::Max);
+        engine.add_layer(LAYER_PATTERN, 50, 1.0, BlendMode::Max);
+        engine.add_layer(LAYER_EXTERNAL, 90, 1.0, BlendMode::Override);
+        engine.add_layer(LAYER_SHAKER, 100, 1.0, BlendMode::Override);
+        engine
+    }
+
+    /// Add a layer, or reconfigure it if a layer with that name already exists.
+    pub fn add_layer(&mut self, name: &'static str, priority: i32, gain: f32, blend: BlendMode) {
+        if let Some(layer) = self.layer_mut(name) {
+            layer.gain = gain;
+            layer.blend = blend;
+            layer.priority = priority;
+        } else {
+            self.layers
+                .push(EffectLayer::new(name, priority, gain, blend));
+        }
+        self.layers.sort_by_key(|layer| layer.priority);
+    }
+
+    /// Change the gain of a layer. Unknown names are ignored.
+    pub fn set_layer_gain(&mut self, name: &str, gain: f32) {
+        if let Some(layer) = self.layer_mut(name) {
+            layer.gain = gain.max(0.0);
+        }
+    }
+
+    /// Drop all waves and the hold level of a layer, silencing it immediately.
+    pub fn clear_layer(&mut self, name: &str) {
+        if let Some(layer) = self.layer_mut(name) {
+            layer.waves.clear();
+            layer.hold = None;
+        }
+    }
+
+    /// Per-layer intensities as of the last update, in composition order.
+    pub fn layer_status(&self) -> Vec<LayerStatus> {
+        self.layers
+            .iter()
+            .map(|layer| LayerStatus {
+                name: layer.name,
+                priority: layer.priority,
+                gain: layer.gain,
+                blend: layer.blend,
+                intensity: layer.intensity,
+                active_waves: layer.waves.len(),
+            })
+            .collect()
+    }
+
+    fn layer_mut(&mut self, name: &str) -> Option<&mut EffectLayer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    /// Start or stop the stick shaker.
+    pub fn set_stick_shaker(&mut self, active: bool, now: f64) {
+        self.shaker.set_active(active, now);
+        if !active {
+            self.clear_layer(LAYER_SHAKER);
+        }
+    }
+
+    /// Play a haptic pattern, replacing the one currently playing.
+    pub fn play_pattern(&mut self, pattern: Pattern, now: f64) {
+        self.pattern = Some((pattern, now));
+    }
+
+    pub fn stop_pattern(&mut self) {
+        self.pattern = None;
+        self.clear_layer(LAYER_PATTERN);
+    }
+
+    /// Hold the external layer at the given intensity, or release it with `None`.
+    pub fn set_override(&mut self, intensity: Option<u8>) {
+        if let Some(layer) = self.layer_mut(LAYER_EXTERNAL) {
+            layer.hold = intensity;
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Mute (`false`) or unmute (`true`) the output. Effects keep running while muted.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Scale the composed output (1.0 = unchanged).
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.max(0.0);
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Switch to another profile. Waves already running keep their shape.
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.filter = Filter3D::new(&profile.filter);
+        self.profile = profile;
+    }
+
+    pub fn params(&self) -> &EngineParams {
+        &self.params
+    }
+
+    pub fn set_tunable(&mut self, tunable: Tunable, value: f32) {
+        tunable.set(&mut self.params, value);
+    }
+
+    /// The sim is paused, in a replay or in a menu, so the output is faded out.
+    pub fn suppressed(&self) -> bool {
+        self.suppressed
+    }
+
+    /// Whether the output changes over time on its own (waves, a pattern, the stick shaker or a
+    /// fade in progress), so `update` has to keep being called. Otherwise the output only
+    /// changes through the other methods, and callers can stop updating until they call one.
+    pub fn needs_updates(&self) -> bool {
+        let fading = if self.suppressed {
+            self.fade > 0.0
+        } else {
+            self.fade < 1.0
+        };
+        fading
+            || self.pattern.is_some()
+            || self.shaker.active_since.is_some()
+            || self.layers.iter().any(|layer| !layer.waves.is_empty())
+    }
+
+    /// Convert the sample into the profile's input (raw g, delta g or jerk), weight the axes ->
+    /// magnitude -> wave with a certain peak intensity starting at `now`, then store it.
+    pub fn push_sample(&mut self, sample: &SimSample, now: f64) -> ProcessedSample {
+        let processed = self.filter_input(sample);
+        let Some((fx, fy, fz)) = processed.filtered else {
+            return processed;
+        };
+
+        // Example magnitude is sqrt(fx^2 + fy^2 + fz^2).
+        let mag = (fx.powi(2) + fy.powi(2) + fz.powi(2)).sqrt();
+
+        // Convert magnitude to [0..255]
+        let scaled = (mag / self.params.max_magnitude) * 255.0;
+        let target_intensity = scaled.clamp(0.0, 255.0) as u8;
+
+        // If the scaled intensity is trivial (like 0), skip spawning wave
+        if target_intensity == 0 {
+            return processed;
+        }
+
+        // Compute the change of magnitude and map it to frequency/sharpness
+        let delta_mag = (mag - self.previous_mag).abs();
+        self.previous_mag = mag; // update for next call
+
+        // Dynamic frequency: e.g., base + some sensitivity * delta
+        let wave_frequency =
+            self.params.base_frequency + self.params.frequency_sensitivity * delta_mag;
+
+        // Dynamic sharpness: e.g., base + some sensitivity * delta
+        // clamp or limit if you like (to avoid going too high)
+        let wave_sharpness = (self.params.base_sharpness
+            + self.params.sharpness_sensitivity * delta_mag)
+            .clamp(1.0, 5.0);
+
+        // Create a new wave event, shaped by the g-force effect of the profile
+        self.wave_seed = self.wave_seed.wrapping_add(1);
+        let wave = WaveEvent {
+            start_time: now,
+            duration: self.profile.gforce.duration,
+            waveform: self.profile.gforce.waveform.clone(),
+            target_intensity,
+            wave_frequency,
+            wave_sharpness,
+            seed: self.wave_seed,
+            weight: (sample.dt / REFERENCE_DT).min(1.0),
+        };
+
+        // Insert wave into the g-force layer
+        let params = &self.params;
+        if let Some(layer) = self
+            .layers
+            .iter_mut()
+            .find(|layer| layer.name == LAYER_GFORCE)
+        {
+            layer.add_wave(wave, params);
+        }
+        processed
+    }
+
+    /// Derive the normalized g-force delta and the weighted, filtered input of the g-force effect
+    /// from a sample. Either is `None` when the sample doesn't give one (first sample, pause,
+    /// replay, menu, sim time jump).
+    fn filter_input(&mut self, sample: &SimSample) -> ProcessedSample {
+        self.suppressed = !sample.is_live();
+        if self.suppressed {
+            // Start over from the first live sample, so the jump across the pause doesn't
+            // come through as one big change.
+            self.reset_input();
+            return ProcessedSample::default();
+        }
+
+        let Some(last) = self.last_sample.replace(*sample) else {
+            return ProcessedSample::default();
+        };
+        // Sim time jumped back (flight reset, replay rewind): the delta is meaningless.
+        if sample.sim_time < last.sim_time {
+            self.reset_input();
+            self.last_sample = Some(*sample);
+            return ProcessedSample::default();
+        }
+        if sample.dt <= 0.0 {
+            return ProcessedSample::default();
+        }
+        let scale = REFERENCE_DT / sample.dt;
+        let (dx, dy, dz) = sample.g_force_delta(&last);
+        let delta = (dx * scale, dy * scale, dz * scale);
+        let last_delta = self.last_delta.replace(delta);
+
+        let (ix, iy, iz) = match self.profile.input.mode {
+            InputMode::RawG => sample.g_force,
+            InputMode::DeltaG => delta,
+            InputMode::Jerk => {
+                let Some((lx, ly, lz)) = last_delta else {
+                    return ProcessedSample {
+                        delta: Some(delta),
+                        filtered: None,
+                    };
+                };
+                (delta.0 - lx, delta.1 - ly, delta.2 - lz)
+            }
+        };
+        let (wx, wy, wz) = self.profile.input.weights;
+        let filtered = self.filter.filter((ix * wx, iy * wy, iz * wz), sample.dt);
+        ProcessedSample {
+            delta: Some(delta),
+            filtered: Some(filtered),
+        }
+    }
+
+    /// Forget the previous samples and the filter history, e.g. when the samples start coming
+    /// from a source with another clock.
+    pub fn reset_input(&mut self) {
+        self.last_sample = None;
+        self.last_delta = None;
+        self.filter.reset();
+        self.previous_mag = 0.0;
+    }
+
+    /// Advance the effects to `now` and return the motor intensity: the composed layers after
+    /// gain, fade and the minimum intensity threshold. Called regularly (e.g. every 20ms).
+    pub fn update(&mut self, now: f64) -> u8 {
+        // The shaker layer holds its pulse level while active. When it stops the hold is
+        // cleared, so we fall straight back to the layers below without a tail.
+        let shaker_level = self
+            .shaker
+            .current_intensity(now, &self.profile.shaker, &self.params);
+        if let Some(layer) = self.layer_mut(LAYER_SHAKER) {
+            layer.hold = shaker_level;
+        }
+
+        // The pattern player holds the pattern's level until the pattern finishes.
+        let pattern_level = self
+            .pattern
+            .as_ref()
+            .and_then(|(pattern, started)| pattern.intensity_at((now - started).max(0.0) as f32));
+        if pattern_level.is_none() {
+            self.pattern = None;
+        }
+        if let Some(layer) = self.layer_mut(LAYER_PATTERN) {
+            layer.hold = pattern_level;
+        }
+
+        // Compose the layers from lowest to highest priority. Inactive layers don't take part.
+        let mut composed = 0u8;
+        for layer in self.layers.iter_mut() {
+            let level = layer.update(now);
+            if level > 0 {
+                composed = layer.blend.blend(composed, level);
+            }
+        }
+
+        // Fade the output out while suppressed, and back in once the sim is live again.
+        let elapsed = self.last_update.map_or(0.0, |last| (now - last).max(0.0));
+        self.last_update = Some(now);
+        let step = elapsed as f32 / SUPPRESS_FADE;
+        self.fade = if self.suppressed {
+            (self.fade - step).max(0.0)
+        } else {
+            (self.fade + step).min(1.0)
+        };
+
+        let composed = (composed as f32 * self.gain * self.fade).round().min(255.0) as u8;
+        if self.enabled && composed >= self.params.min_motor_intensity {
+            composed
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(sim_time: f64, normal_g: f32) -> SimSample {
+        SimSample {
+            sim_time,
+            dt: REFERENCE_DT,
+            g_force: (0.0, 0.0, normal_g),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_g_force_step_spawns_wave() {
+        let mut engine = VibrationEngine::new(Profile::default(), EngineParams::default());
+        let dt = REFERENCE_DT as f64;
+        engine.push_sample(&sample(0.0, 1.0), 0.0);
+        engine.push_sample(&sample(dt, 1.0), dt);
+        assert_eq!(engine.update(dt), 0);
+
+        let processed = engine.push_sample(&sample(2.0 * dt, 2.0), 2.0 * dt);
+        assert_eq!(processed.delta, Some((0.0, 0.0, 1.0)));
+        assert!(engine.needs_updates());
+        let peak = (0..10)
+            .map(|step| engine.update(2.0 * dt + step as f64 * 0.02))
+            .max()
+            .unwrap();
+        assert!(peak > 0);
+        // The wave is over after its duration.
+        assert_eq!(engine.update(3.0), 0);
+        assert!(!engine.needs_updates());
+    }
+
+    #[test]
+    fn test_pause_suppresses_and_restarts_input() {
+        let mut engine = VibrationEngine::new(Profile::default(), EngineParams::default());
+        engine.push_sample(&sample(0.0, 1.0), 0.0);
+        engine.set_override(Some(200));
+        assert_eq!(engine.update(0.0), 200);
+
+        let paused = SimSample {
+            paused: true,
+            ..sample(1.0, 1.0)
+        };
+        assert_eq!(engine.push_sample(&paused, 1.0), ProcessedSample::default());
+        assert!(engine.suppressed());
+        engine.update(1.0);
+        assert_eq!(engine.update(1.0 + SUPPRESS_FADE as f64), 0);
+
+        // The first sample after the pause is the new baseline, not a 2 g jump.
+        let resumed = engine.push_sample(&sample(2.0, 3.0), 2.0);
+        assert_eq!(resumed.delta, None);
+        assert!(!engine.suppressed());
+    }
+
+    #[test]
+    fn test_shaker_overrides_waves() {
+        let mut engine = VibrationEngine::new(Profile::default(), EngineParams::default());
+        engine.set_stick_shaker(true, 0.0);
+        let params = EngineParams::default();
+        let level = engine.update(0.0);
+        assert!(level >= params.shaker_low_intensity && level <= params.shaker_intensity);
+        engine.set_stick_shaker(false, 0.1);
+        assert_eq!(engine.update(0.1), 0);
+        assert!(!engine.needs_updates());
+    }
+
+    fn wave(start_time: f64, target_intensity: u8) -> WaveEvent {
+        WaveEvent {
+            start_time,
+            duration: 0.25,
+            waveform: Waveform::HalfSine,
+            target_intensity,
+            wave_frequency: 1.0,
+            wave_sharpness: 1.0,
+            seed: 0,
+            weight: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_wave_pool_stays_bounded_at_any_frame_rate() {
+        let params = EngineParams::default();
+        for fps in [30.0, 144.0, 1000.0] {
+            let mut engine = VibrationEngine::new(Profile::default(), params.clone());
+            let dt = 1.0 / fps;
+            for frame in 0..(fps as usize) {
+                let now = frame as f64 * dt;
+                let mut sample = sample(now, 1.0 + (frame % 2) as f32);
+                sample.dt = dt as f32;
+                engine.push_sample(&sample, now);
+                engine.update(now);
+            }
+            let gforce = &engine.layer_status()[0];
+            assert_eq!(gforce.name, LAYER_GFORCE);
+            assert!(gforce.active_waves > 0 && gforce.active_waves <= params.max_waves);
+        }
+    }
+
+    #[test]
+    fn test_wave_merge_policies() {
+        let merged = |merge: WaveMerge, second: WaveEvent| {
+            let params = EngineParams {
+                max_waves: 2,
+                wave_merge: merge,
+                ..Default::default()
+            };
+            let mut layer = EffectLayer::new(LAYER_GFORCE, 0, 1.0, BlendMode::Max);
+            layer.add_wave(wave(0.0, 100), &params);
+            layer.add_wave(second, &params);
+            layer
+                .waves
+                .iter()
+                .map(|wave| (wave.start_time, wave.duration, wave.target_intensity))
+                .collect::<Vec<_>>()
+        };
+
+        // Near-simultaneous waves become one.
+        assert_eq!(
+            merged(WaveMerge::ReplaceWeakest, wave(0.015625, 150)),
+            vec![(0.015625, 0.25, 150)]
+        );
+        assert_eq!(
+            merged(WaveMerge::ReplaceWeakest, wave(0.015625, 50)),
+            vec![(0.0, 0.25, 100)]
+        );
+        assert_eq!(
+            merged(WaveMerge::ExtendExisting, wave(0.015625, 50)),
+            vec![(0.0, 0.25, 100)]
+        );
+        assert_eq!(
+            merged(WaveMerge::Sum, wave(0.015625, 200)),
+            vec![(0.0, 0.25, 255)]
+        );
+        // Waves further apart are kept apart while there's room.
+        assert_eq!(merged(WaveMerge::Sum, wave(0.1, 50)).len(), 2);
+
+        // A full layer replaces its weakest wave, unless the new one is weaker still.
+        let params = EngineParams {
+            max_waves: 2,
+            ..Default::default()
+        };
+        let mut layer = EffectLayer::new(LAYER_GFORCE, 0, 1.0, BlendMode::Max);
+        for (start, intensity) in [(0.0, 200), (0.1, 20), (0.15, 60), (0.2, 10)] {
+            layer.add_wave(wave(start, intensity), &params);
+        }
+        let targets: Vec<_> = layer.waves.iter().map(|w| w.target_intensity).collect();
+        assert_eq!(targets, vec![200, 60]);
+    }
+
+    #[test]
+    fn test_full_layer_keeps_new_wave_timing() {
+        let params = EngineParams {
+            max_waves: 2,
+            ..Default::default()
+        };
+        let mut layer = EffectLayer::new(LAYER_GFORCE, 0, 1.0, BlendMode::Max);
+        layer.add_wave(
+            WaveEvent {
+                duration: 1.0,
+                ..wave(0.0, 200)
+            },
+            &params,
+        );
+        // The weakest wave ends at 0.35, just after the new one starts.
+        layer.add_wave(wave(0.1, 20), &params);
+        layer.add_wave(wave(0.34, 60), &params);
+        layer.update(0.5);
+        let timings: Vec<_> = layer
+            .waves
+            .iter()
+            .map(|wave| (wave.start_time, wave.target_intensity))
+            .collect();
+        assert_eq!(timings, vec![(0.0, 200), (0.34, 60)]);
+    }
+
+    #[test]
+    fn test_sum_is_independent_of_frame_rate() {
+        let params = EngineParams {
+            wave_merge: WaveMerge::Sum,
+            ..Default::default()
+        };
+        // The same input over the merge window, sampled at 60 and at 120 FPS.
+        let summed = |fps: f64| {
+            let mut layer = EffectLayer::new(LAYER_GFORCE, 0, 1.0, BlendMode::Max);
+            for frame in 0..4 {
+                let start_time = frame as f64 / fps;
+                if start_time > params.merge_window as f64 {
+                    break;
+                }
+                layer.add_wave(
+                    WaveEvent {
+                        weight: 1.0 / (fps as f32 * REFERENCE_DT),
+                        ..wave(start_time, 50)
+                    },
+                    &params,
+                );
+            }
+            layer.waves[0].target_intensity
+        };
+        assert_eq!(summed(60.0), 100);
+        assert_eq!(summed(120.0), 100);
+    }
+
+    #[test]
+    fn test_tunables() {
+        let mut params = EngineParams::default();
+        Tunable::MaxMagnitude.set(&mut params, 100.0);
+        assert_eq!(params.max_magnitude, 5.0);
+        assert_eq!(
+            Tunable::from_name("base_sharpness"),
+            Some(Tunable::BaseSharpness)
+        );
+        assert_eq!(Tunable::from_name("high_pass_alpha"), None);
+
+        params.assign("base_frequency=2.5").unwrap();
+        assert_eq!(params.base_frequency, 2.5);
+        params.assign("wave_merge=2").unwrap();
+        assert_eq!(params.wave_merge, WaveMerge::Sum);
+        assert_eq!(Tunable::WaveMerge.get(&params), 2.0);
+        Tunable::MaxWaves.set(&mut params, 0.0);
+        assert_eq!(params.max_waves, 1);
+        assert!(params.assign("base_frequency").is_err());
+        assert!(params.assign("high_pass_alpha=0.9").is_err());
+        assert!(params.assign("merge_window=NaN").is_err());
+        assert!(params.assign("min_motor_intensity=inf").is_err());
+        assert_eq!(params.merge_window, EngineParams::default().merge_window);
+    }
+
+    #[test]
+    fn test_params_validation() {
+        assert_eq!(EngineParams::default().validate(), Ok(()));
+        let invalid = [
+            EngineParams {
+                max_magnitude: 0.0,
+                ..Default::default()
+            },
+            EngineParams {
+                base_frequency: f32::NAN,
+                ..Default::default()
+            },
+            EngineParams {
+                sharpness_sensitivity: f32::INFINITY,
+                ..Default::default()
+            },
+            EngineParams {
+                merge_window: -0.1,
+                ..Default::default()
+            },
+            EngineParams {
+                max_waves: 0,
+                ..Default::default()
+            },
+            EngineParams {
+                max_waves: 10_000,
+                ..Default::default()
+            },
+        ];
+        for params in invalid {
+            assert!(params.validate().is_err(), "{params:?} was accepted");
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! A small UDP protocol through which any application can drive the motor: g-force triples,
+//! named effect triggers and direct intensity overrides.
+//!
+//! Every datagram carries exactly one message, either as JSON or in a compact binary form. A
+//! datagram whose first byte is `{` is JSON; anything else must be binary.
+//!
+//! # JSON
+//!
+//! ```json
+//! { "version": 1, "type": "g_force", "g": [0.02, -0.1, 1.35] }
+//! { "version": 1, "type": "effect", "name": "Double thump" }
+//! { "version": 1, "type": "effect", "name": "stick_shaker", "active": false }
+//! { "version": 1, "type": "intensity", "value": 200 }
+//! { "version": 1, "type": "intensity", "value": null }
+//! ```
+//!
+//! `version` may be left out and then means 1; any other version is rejected.
+//!
+//! - `g_force`: side, axial and normal g, the same axes as X-Plane's `gforce_side`, `gforce_axil`
+//!   and `gforce_normal`. Send them at a steady rate (30 to 60 per second): the engine reacts to
+//!   changes between messages, not to the values themselves.
+//! - `effect`: `active` defaults to `true`. `stick_shaker` starts or stops the stick shaker; any
+//!   other name plays the pattern with that name (case-insensitive), and `active: false` stops
+//!   the pattern playing.
+//! - `intensity`: holds the motor at `value` (0 to 255) above everything but the stick shaker,
+//!   until a message with `null` releases it.
+//!
+//! # Binary
+//!
+//! All numbers are little-endian.
+//!
+//! | Offset | Size | Content                                             |
+//! |--------|------|-----------------------------------------------------|
+//! | 0      | 4    | `XAUH`                                              |
+//! | 4      | 1    | Version, 1                                          |
+//! | 5      | 1    | Type: 1 = g-force, 2 = effect, 3 = intensity        |
+//! | 6      |      | Payload                                             |
+//!
+//! - g-force: three `f32`, side, axial and normal g.
+//! - effect: one byte, 1 to start and 0 to stop, then the UTF-8 name up to the end of the datagram.
+//! - intensity: one byte, 1 to hold and 0 to release, then the intensity byte.
+
+use crate::engine::{VibrationEngine, MIN_SAMPLE_DT};
+use crate::pattern::Pattern;
+use crate::sample::SimSample;
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+/// Port the listener uses unless told otherwise.
+pub const DEFAULT_PORT: u16 = 49010;
+
+/// Version of the protocol this module speaks.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Effect name that controls the stick shaker instead of playing a pattern.
+pub const EFFECT_STICK_SHAKER: &str = "stick_shaker";
+
+/// First bytes of a binary message.
+const BINARY_MAGIC: &[u8; 4] = b"XAUH";
+
+/// Pause after a failed receive, doubled with each further failure in a row.
+const RECV_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Failed receives in a row after which the listener gives up.
+const MAX_RECV_FAILURES: u32 = 8;
+
+const TYPE_G_FORCE: u8 = 1;
+const TYPE_EFFECT: u8 = 2;
+const TYPE_INTENSITY: u8 = 3;
+
+/// One message of the protocol.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExternalMessage {
+    /// Side, axial and normal g.
+    GForce { g: (f32, f32, f32) },
+    /// Start or stop a named effect.
+    Effect {
+        name: String,
+        #[serde(default = "default_active")]
+        active: bool,
+    },
+    /// Hold the motor at an intensity, or release it with `None`.
+    Intensity { value: Option<u8> },
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// JSON message with the version, which serde's internally tagged enums can't carry alongside.
+#[derive(Deserialize)]
+struct JsonEnvelope {
+    #[serde(default = "default_version")]
+    version: u8,
+    #[serde(flatten)]
+    message: ExternalMessage,
+}
+
+fn default_version() -> u8 {
+    PROTOCOL_VERSION
+}
+
+impl ExternalMessage {
+    /// Parse one datagram, JSON or binary.
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let message = if bytes.first() == Some(&b'{') {
+            Self::from_json(bytes)?
+        } else {
+            Self::from_binary(bytes)?
+        };
+        message.validate()?;
+        Ok(message)
+    }
+
+    /// Reject values the engine can't work with. Binary messages can carry NaN and infinity,
+    /// and JSON numbers too large for an `f32` become infinity.
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::GForce { g: (x, y, z) } if ![x, y, z].iter().all(|axis| axis.is_finite()) => {
+                Err(format!("g-force must be finite, got ({x}, {y}, {z})"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        let envelope: JsonEnvelope =
+            serde_json::from_slice(bytes).map_err(|e| format!("Invalid message JSON: {e}"))?;
+        check_version(envelope.version)?;
+        Ok(envelope.message)
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<Self, String> {
+        let Some(rest) = bytes.strip_prefix(BINARY_MAGIC.as_slice()) else {
+            return Err("Not a haptic message".to_string());
+        };
+        let [version, kind, payload @ ..] = rest else {
+            return Err("Message too short".to_string());
+        };
+        check_version(*version)?;
+        match (*kind, payload) {
+            (TYPE_G_FORCE, payload) if payload.len() == 12 => {
+                let axis =
+                    |i: usize| f32::from_le_bytes(payload[i * 4..i * 4 + 4].try_into().unwrap());
+                Ok(Self::GForce {
+                    g: (axis(0), axis(1), axis(2)),
+                })
+            }
+            (TYPE_EFFECT, [active, name @ ..]) => Ok(Self::Effect {
+                name: String::from_utf8(name.to_vec())
+                    .map_err(|_| "Effect name is not UTF-8".to_string())?,
+                active: *active != 0,
+            }),
+            (TYPE_INTENSITY, [hold, value]) => Ok(Self::Intensity {
+                value: (*hold != 0).then_some(*value),
+            }),
+            (TYPE_G_FORCE | TYPE_EFFECT | TYPE_INTENSITY, _) => {
+                Err(format!("Wrong payload length for message type {kind}"))
+            }
+            _ => Err(format!("Unknown message type {kind}")),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::to_value(self).expect("messages always serialize");
+        value["version"] = PROTOCOL_VERSION.into();
+        value.to_string()
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.push(PROTOCOL_VERSION);
+        match self {
+            Self::GForce { g } => {
+                bytes.push(TYPE_G_FORCE);
+                for axis in [g.0, g.1, g.2] {
+                    bytes.extend_from_slice(&axis.to_le_bytes());
+                }
+            }
+            Self::Effect { name, active } => {
+                bytes.push(TYPE_EFFECT);
+                bytes.push(*active as u8);
+                bytes.extend_from_slice(name.as_bytes());
+            }
+            Self::Intensity { value } => {
+                bytes.push(TYPE_INTENSITY);
+                bytes.push(value.is_some() as u8);
+                bytes.push(value.unwrap_or(0));
+            }
+        }
+        bytes
+    }
+}
+
+fn check_version(version: u8) -> Result<(), String> {
+    if version == PROTOCOL_VERSION {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unsupported protocol version {version}, expected {PROTOCOL_VERSION}"
+        ))
+    }
+}
+
+/// Applies messages to an engine: turns g-force triples into samples and looks up effects.
+pub struct ExternalInput {
+    patterns: Vec<Pattern>,
+    last_g_force: Option<f64>,
+}
+
+impl ExternalInput {
+    /// `patterns` are the ones `effect` messages can play by name.
+    pub fn new(patterns: Vec<Pattern>) -> Self {
+        Self {
+            patterns,
+            last_g_force: None,
+        }
+    }
+
+    /// Apply a message received at `now` (seconds, the clock the engine is updated with).
+    pub fn apply(
+        &mut self,
+        engine: &mut VibrationEngine,
+        message: ExternalMessage,
+        now: f64,
+    ) -> Result<(), String> {
+        match message {
+            ExternalMessage::GForce { g } => {
+                // Messages have no sim time; the receive time stands in for it, clamped so a
+                // burst of datagrams doesn't make a near-zero dt.
+                let dt = self
+                    .last_g_force
+                    .map_or(0.0, |last| ((now - last) as f32).max(MIN_SAMPLE_DT));
+                self.last_g_force = Some(now);
+                let sample = SimSample {
+                    sim_time: now,
+                    dt,
+                    g_force: g,
+                    ..Default::default()
+                };
+                engine.push_sample(&sample, now);
+            }
+            ExternalMessage::Effect { name, active } if name == EFFECT_STICK_SHAKER => {
+                engine.set_stick_shaker(active, now);
+            }
+            ExternalMessage::Effect { active: false, .. } => engine.stop_pattern(),
+            ExternalMessage::Effect { name, .. } => {
+                let pattern = self
+                    .patterns
+                    .iter()
+                    .find(|pattern| pattern.name.eq_ignore_ascii_case(&name))
+                    .ok_or(format!("Unknown effect \"{name}\""))?;
+                engine.play_pattern(pattern.clone(), now);
+            }
+            ExternalMessage::Intensity { value } => engine.set_override(value),
+        }
+        Ok(())
+    }
+}
+
+/// Receive messages on `address` in a background thread. Invalid datagrams are reported to
+/// stderr and skipped; the thread ends when the receiver is dropped, or after
+/// `MAX_RECV_FAILURES` failed receives in a row.
+pub fn listen(address: SocketAddr) -> Result<Receiver<ExternalMessage>, String> {
+    let socket =
+        UdpSocket::bind(address).map_err(|e| format!("Failed to listen on {address}: {e}"))?;
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        let mut failures = 0;
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => {
+                    failures = 0;
+                    received
+                }
+                Err(e) if failures + 1 >= MAX_RECV_FAILURES => {
+                    eprintln!(
+                        "Failed to receive a haptic message: {e}; giving up after \
+                         {MAX_RECV_FAILURES} failures in a row"
+                    );
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Failed to receive a haptic message: {e}");
+                    // Persistent errors would spin otherwise.
+                    thread::sleep(RECV_BACKOFF * 2u32.pow(failures));
+                    failures += 1;
+                    continue;
+                }
+            };
+            match ExternalMessage::parse(&buf[..len]) {
+                Ok(message) => {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("Ignoring message from {from}: {e}"),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineParams;
+    use crate::profile::Profile;
+    use std::time::Duration;
+
+    fn messages() -> Vec<ExternalMessage> {
+        vec![
+            ExternalMessage::GForce {
+                g: (0.25, -0.5, 1.75),
+            },
+            ExternalMessage::Effect {
+                name: "Double thump".to_string(),
+                active: true,
+            },
+            ExternalMessage::Effect {
+                name: EFFECT_STICK_SHAKER.to_string(),
+                active: false,
+            },
+            ExternalMessage::Intensity { value: Some(200) },
+            ExternalMessage::Intensity { value: None },
+        ]
+    }
+
+    #[test]
+    fn test_json_messages() {
+        assert_eq!(
+            ExternalMessage::parse(br#"{"type":"g_force","g":[0.25,-0.5,1.75]}"#),
+            Ok(messages()[0].clone())
+        );
+        assert_eq!(
+            ExternalMessage::parse(br#"{"version":1,"type":"effect","name":"Double thump"}"#),
+            Ok(messages()[1].clone())
+        );
+        assert_eq!(
+            ExternalMessage::parse(br#"{"type":"intensity","value":null}"#),
+            Ok(ExternalMessage::Intensity { value: None })
+        );
+        assert!(
+            ExternalMessage::parse(br#"{"version":2,"type":"intensity","value":1}"#)
+                .unwrap_err()
+                .contains("version 2")
+        );
+        assert!(ExternalMessage::parse(br#"{"type":"intensity","value":300}"#).is_err());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for message in messages() {
+            assert_eq!(
+                ExternalMessage::parse(message.to_json().as_bytes()),
+                Ok(message.clone())
+            );
+            assert_eq!(ExternalMessage::parse(&message.to_binary()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_invalid_binary() {
+        assert!(ExternalMessage::parse(b"XAUX\x01\x03\x01\x10").is_err());
+        assert!(ExternalMessage::parse(b"XAUH\x02\x03\x01\x10").is_err());
+        assert!(ExternalMessage::parse(b"XAUH\x01\x03\x01").is_err());
+        assert!(ExternalMessage::parse(b"XAUH\x01\x01\0\0\0\0").is_err());
+        assert!(ExternalMessage::parse(b"XAUH\x01\x09").is_err());
+        assert!(ExternalMessage::parse(b"XAUH").is_err());
+    }
+
+    #[test]
+    fn test_non_finite_g_force_is_rejected() {
+        for g in [
+            (f32::NAN, 0.0, 1.0),
+            (0.0, f32::INFINITY, 1.0),
+            (0.0, 0.0, f32::NEG_INFINITY),
+        ] {
+            let binary = ExternalMessage::GForce { g }.to_binary();
+            assert!(ExternalMessage::parse(&binary).is_err(), "{g:?}");
+        }
+        assert!(ExternalMessage::parse(br#"{"type":"g_force","g":[0.0,1e39,1.0]}"#).is_err());
+    }
+
+    #[test]
+    fn test_apply_to_engine() {
+        let pattern = Pattern::from_json(
+            r#"{ "name": "Thump", "segments": [{ "intensity": 1.0, "duration": 1.0 }] }"#,
+        )
+        .unwrap();
+        let mut engine = VibrationEngine::new(Profile::default(), EngineParams::default());
+        let mut input = ExternalInput::new(vec![pattern]);
+
+        let mut apply = |message: &[u8], now: f64, engine: &mut VibrationEngine| {
+            input.apply(engine, ExternalMessage::parse(message).unwrap(), now)
+        };
+        apply(br#"{"type":"intensity","value":120}"#, 0.0, &mut engine).unwrap();
+        assert_eq!(engine.update(0.0), 120);
+        apply(br#"{"type":"intensity","value":null}"#, 0.1, &mut engine).unwrap();
+        apply(br#"{"type":"effect","name":"thump"}"#, 0.1, &mut engine).unwrap();
+        assert_eq!(engine.update(0.2), 255);
+        apply(
+            br#"{"type":"effect","name":"thump","active":false}"#,
+            0.3,
+            &mut engine,
+        )
+        .unwrap();
+        assert_eq!(engine.update(0.3), 0);
+        assert!(apply(br#"{"type":"effect","name":"missing"}"#, 0.4, &mut engine).is_err());
+    }
+
+    #[test]
+    fn test_listen() {
+        let probe = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = probe.local_addr().unwrap();
+        drop(probe);
+        let rx = listen(address).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"garbage", address).unwrap();
+        for message in messages() {
+            sender.send_to(&message.to_binary(), address).unwrap();
+        }
+        let received: Vec<_> = (0..messages().len())
+            .map(|_| rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect();
+        assert_eq!(received, messages());
+    }
+}
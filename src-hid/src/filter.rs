@@ -0,0 +1,202 @@
+use crate::validate::check_positive;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+/// Cutoffs of one axis' filter chain. A high-pass alone keeps quick changes (runway texture,
+/// touchdowns); adding a low-pass below that range makes it a band-pass, e.g. to keep only
+/// turbulence. `None` skips a stage.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisFilter {
+    /// Changes slower than this (in Hz) are removed.
+    pub high_pass_hz: Option<f32>,
+    /// Changes faster than this (in Hz) are removed.
+    pub low_pass_hz: Option<f32>,
+}
+
+impl Default for AxisFilter {
+    /// About the same response as the old fixed alpha of 0.9 at 60 FPS.
+    fn default() -> Self {
+        Self {
+            high_pass_hz: Some(1.0),
+            low_pass_hz: None,
+        }
+    }
+}
+
+impl AxisFilter {
+    pub fn validate(&self, axis: &str) -> Result<(), String> {
+        for (stage, cutoff) in [
+            ("high_pass_hz", self.high_pass_hz),
+            ("low_pass_hz", self.low_pass_hz),
+        ] {
+            if let Some(cutoff) = cutoff {
+                check_positive(stage, cutoff).map_err(|e| format!("{axis}: {e}"))?;
+            }
+        }
+        if let (Some(high), Some(low)) = (self.high_pass_hz, self.low_pass_hz) {
+            if low <= high {
+                return Err(format!(
+                    "{axis}: low_pass_hz ({low}) must be above high_pass_hz ({high})"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Filter cutoffs for the (side, axial, normal) axes of the g-force input.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub side: AxisFilter,
+    pub axial: AxisFilter,
+    pub normal: AxisFilter,
+}
+
+impl FilterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.side.validate("filter.side")?;
+        self.axial.validate("filter.axial")?;
+        self.normal.validate("filter.normal")
+    }
+}
+
+/// Time constant of a first-order filter with the given cutoff.
+fn time_constant(cutoff_hz: f32) -> f32 {
+    1.0 / (TAU * cutoff_hz)
+}
+
+/// State of one axis' high-pass and low-pass stages.
+#[derive(Clone, Debug, Default)]
+struct AxisState {
+    config: AxisFilter,
+    prev_input: f32,
+    high_pass_output: f32,
+    low_pass_output: f32,
+}
+
+impl AxisState {
+    fn filter(&mut self, input: f32, dt: f32) -> f32 {
+        let mut value = input;
+        if let Some(cutoff) = self.config.high_pass_hz {
+            let rc = time_constant(cutoff);
+            let alpha = rc / (rc + dt);
+            self.high_pass_output = alpha * (self.high_pass_output + input - self.prev_input);
+            value = self.high_pass_output;
+        }
+        self.prev_input = input;
+        if let Some(cutoff) = self.config.low_pass_hz {
+            let alpha = dt / (time_constant(cutoff) + dt);
+            self.low_pass_output += alpha * (value - self.low_pass_output);
+            value = self.low_pass_output;
+        }
+        value
+    }
+}
+
+/// One-pole filters per axis, with the smoothing factors derived from the actual time between
+/// samples so the cutoffs hold at any frame rate.
+#[derive(Clone, Debug)]
+pub struct Filter3D {
+    axes: [AxisState; 3],
+}
+
+impl Filter3D {
+    pub fn new(config: &FilterConfig) -> Self {
+        let axis = |config: AxisFilter| AxisState {
+            config,
+            ..Default::default()
+        };
+        Self {
+            axes: [axis(config.side), axis(config.axial), axis(config.normal)],
+        }
+    }
+
+    /// Filter the given (side, axial, normal) input, `dt` seconds after the previous one.
+    pub fn filter(&mut self, input: (f32, f32, f32), dt: f32) -> (f32, f32, f32) {
+        let [x, y, z] = &mut self.axes;
+        (
+            x.filter(input.0, dt),
+            y.filter(input.1, dt),
+            z.filter(input.2, dt),
+        )
+    }
+
+    /// Forget the filter history, e.g. after a jump in sim time.
+    pub fn reset(&mut self) {
+        for axis in &mut self.axes {
+            *axis = AxisState {
+                config: axis.config,
+                ..Default::default()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_axis(config: AxisFilter) -> Filter3D {
+        Filter3D::new(&FilterConfig {
+            side: config,
+            axial: config,
+            normal: config,
+        })
+    }
+
+    #[test]
+    fn test_high_pass_removes_steady_input() {
+        let mut filter = single_axis(AxisFilter::default());
+        let mut output = 0.0;
+        for _ in 0..600 {
+            output = filter.filter((1.0, 1.0, 1.0), 1.0 / 60.0).0;
+        }
+        assert!(output.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_cutoff_is_independent_of_frame_rate() {
+        // A step held for half a second decays the same at 30 and 120 samples per second.
+        let settle = |rate: usize| {
+            let mut filter = single_axis(AxisFilter::default());
+            let mut output = 0.0;
+            for _ in 0..rate / 2 {
+                output = filter.filter((1.0, 0.0, 0.0), 1.0 / rate as f32).0;
+            }
+            output
+        };
+        assert!((settle(30) - settle(120)).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_low_pass_smooths_and_passes_steady_input() {
+        let mut filter = single_axis(AxisFilter {
+            high_pass_hz: None,
+            low_pass_hz: Some(2.0),
+        });
+        let first = filter.filter((1.0, 1.0, 1.0), 1.0 / 60.0).0;
+        assert!(first > 0.0 && first < 0.5);
+        let mut output = first;
+        for _ in 0..600 {
+            output = filter.filter((1.0, 1.0, 1.0), 1.0 / 60.0).0;
+        }
+        assert!((output - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_invalid_cutoffs_are_rejected() {
+        let band = AxisFilter {
+            high_pass_hz: Some(5.0),
+            low_pass_hz: Some(2.0),
+        };
+        assert!(band.validate("side").is_err());
+        let negative = AxisFilter {
+            high_pass_hz: Some(-1.0),
+            low_pass_hz: None,
+        };
+        assert!(negative.validate("side").is_err());
+        assert!(AxisFilter::default().validate("side").is_ok());
+    }
+}
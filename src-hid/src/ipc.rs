@@ -0,0 +1,606 @@
+//! Local IPC between the desktop app and the running X-Plane plugin.
+//!
+//! The plugin serves a local socket: a Unix domain socket, or on other platforms a TCP socket on
+//! the loopback interface whose port is written to the endpoint file. Messages are JSON, one per
+//! line. A client first sends `hello` with its protocol version; the server answers with its own
+//! and closes the connection if they differ. Every request after that gets exactly one response.
+//!
+//! The endpoint also tells who owns the device: while a plugin serves it, the plugin writes to
+//! the stick and everyone else has to go through it (see `device_owner`). Only the plugin
+//! advertises ownership, so the plugin always wins: the app checks before every device command
+//! and otherwise opens the stick itself, and a plugin starting meanwhile writes to it at once. An
+//! app command already running (a test ramp or pattern preview) then overlaps with the plugin
+//! until it ends. The command-line tools only check when they start (see `ensure_device_free`).
+
+use crate::pattern::Pattern;
+use crate::profile::Profile;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Version of the protocol; bumped on every incompatible change of the messages.
+pub const IPC_VERSION: u32 = 1;
+
+/// How long a connection waits for the plugin's main thread to answer a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Pause after a failed accept, doubled with each further failure in a row.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Failed accepts in a row after which the server gives up accepting connections.
+const MAX_ACCEPT_FAILURES: u32 = 8;
+
+/// How long the client waits for a response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Reported when the device can't be written because the plugin owns it.
+pub const DEVICE_IN_USE: &str = "Device in use by X-Plane plugin";
+
+/// Where the plugin serves and the app connects, unless told otherwise.
+pub fn default_endpoint() -> PathBuf {
+    let name = if cfg!(unix) {
+        "xa-ursa-minor.sock"
+    } else {
+        "xa-ursa-minor.port"
+    };
+    std::env::temp_dir().join(name)
+}
+
+#[cfg(unix)]
+mod transport {
+    use std::io;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+
+    pub type Listener = UnixListener;
+    pub type Stream = UnixStream;
+
+    /// Identifies the socket file, so a server only removes the one it created.
+    pub type EndpointId = (u64, u64);
+
+    pub fn bind(endpoint: &Path) -> io::Result<Listener> {
+        if connect(endpoint).is_ok() {
+            return Err(super::endpoint_in_use());
+        }
+        // Nobody answers, so the socket file was left behind by a crashed plugin and would make
+        // the bind fail.
+        let _ = std::fs::remove_file(endpoint);
+        UnixListener::bind(endpoint)
+    }
+
+    pub fn endpoint_id(endpoint: &Path) -> Option<EndpointId> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(endpoint).ok()?;
+        Some((metadata.dev(), metadata.ino()))
+    }
+
+    pub fn connect(endpoint: &Path) -> io::Result<Stream> {
+        UnixStream::connect(endpoint)
+    }
+}
+
+#[cfg(not(unix))]
+mod transport {
+    use std::io;
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::path::Path;
+
+    pub type Listener = TcpListener;
+    pub type Stream = TcpStream;
+
+    /// Identifies the endpoint file by the port it names, so a server only removes its own.
+    pub type EndpointId = String;
+
+    pub fn bind(endpoint: &Path) -> io::Result<Listener> {
+        if connect(endpoint).is_ok() {
+            return Err(super::endpoint_in_use());
+        }
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        std::fs::write(endpoint, listener.local_addr()?.port().to_string())?;
+        Ok(listener)
+    }
+
+    pub fn endpoint_id(endpoint: &Path) -> Option<EndpointId> {
+        std::fs::read_to_string(endpoint).ok()
+    }
+
+    pub fn connect(endpoint: &Path) -> io::Result<Stream> {
+        let port = std::fs::read_to_string(endpoint)?
+            .trim()
+            .parse::<u16>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+    }
+}
+
+fn endpoint_in_use() -> std::io::Error {
+    std::io::Error::new(ErrorKind::AddrInUse, "another plugin is already serving it")
+}
+
+/// A message from the app to the plugin.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpcRequest {
+    /// First message of every connection.
+    Hello {
+        version: u32,
+    },
+    Status,
+    /// Replace the profile with the same name, or add it, and select it.
+    SetProfile {
+        profile: Profile,
+    },
+    /// The motor intensity last written.
+    Intensity,
+    PlayPattern {
+        pattern: Pattern,
+    },
+    StopPattern,
+    /// Set the backlight level (0..255) immediately.
+    SetBacklight {
+        level: u8,
+    },
+}
+
+/// A message from the plugin to the app.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpcResponse {
+    Hello { version: u32 },
+    Status { status: PluginStatus },
+    Intensity { intensity: u8 },
+    Ok,
+    Error { message: String },
+}
+
+/// What the plugin reports about itself.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PluginStatus {
+    pub enabled: bool,
+    pub gain: f32,
+    /// Name of the selected profile.
+    pub profile: String,
+    pub profiles: Vec<String>,
+    /// Names of the loaded patterns.
+    pub patterns: Vec<String>,
+    pub intensity: u8,
+    /// Serial number of the stick the plugin writes to; `None` while it isn't connected.
+    pub serial_number: Option<String>,
+}
+
+fn send_message<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<(), String> {
+    let mut line = serde_json::to_string(message).map_err(|e| format!("Failed to encode: {e}"))?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .and_then(|_| writer.flush())
+        .map_err(|e| format!("Failed to send: {e}"))
+}
+
+/// Read the next message; `None` once the other side closed the connection.
+fn read_message<T: DeserializeOwned>(reader: &mut impl BufRead) -> Result<Option<T>, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => serde_json::from_str(&line)
+            .map(Some)
+            .map_err(|e| format!("Invalid message: {e}")),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            Err("Timed out waiting for an answer".to_string())
+        }
+        Err(e) => Err(format!("Failed to receive: {e}")),
+    }
+}
+
+/// A request waiting for the plugin to answer it.
+pub struct IpcCall {
+    pub request: IpcRequest,
+    reply: Sender<IpcResponse>,
+}
+
+impl IpcCall {
+    pub fn reply(self, response: IpcResponse) {
+        // The connection may have timed out or closed meanwhile; nothing to do then.
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Serves the endpoint in background threads and hands every request to whoever polls
+/// `calls`, so they can be answered on the thread that owns the plugin state.
+pub struct IpcServer {
+    endpoint: PathBuf,
+    /// The endpoint file as this server created it; `None` if it vanished right away.
+    endpoint_id: Option<transport::EndpointId>,
+    stop: Arc<AtomicBool>,
+    calls: Receiver<IpcCall>,
+}
+
+impl IpcServer {
+    /// Serve `endpoint`. Fails if another server answers there already. Failures of the accept
+    /// thread are passed to `on_error`; after `MAX_ACCEPT_FAILURES` in a row it stops accepting,
+    /// while open connections keep working.
+    pub fn start(
+        endpoint: &Path,
+        on_error: impl Fn(&str) + Send + 'static,
+    ) -> Result<Self, String> {
+        let listener = transport::bind(endpoint)
+            .map_err(|e| format!("Failed to serve {}: {e}", endpoint.display()))?;
+        let endpoint_id = transport::endpoint_id(endpoint);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, calls) = mpsc::channel();
+        let accept_stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut failures = 0;
+            for stream in listener.incoming() {
+                if accept_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        failures = 0;
+                        let tx = tx.clone();
+                        thread::spawn(move || serve_connection(stream, tx));
+                    }
+                    Err(e) if failures + 1 >= MAX_ACCEPT_FAILURES => {
+                        on_error(&format!(
+                            "Failed to accept an IPC connection: {e}; giving up after \
+                             {MAX_ACCEPT_FAILURES} failures in a row"
+                        ));
+                        break;
+                    }
+                    Err(e) => {
+                        on_error(&format!("Failed to accept an IPC connection: {e}"));
+                        // Persistent errors (e.g. out of file descriptors) would spin otherwise.
+                        thread::sleep(ACCEPT_BACKOFF * 2u32.pow(failures));
+                        failures += 1;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            endpoint: endpoint.to_path_buf(),
+            endpoint_id,
+            stop,
+            calls,
+        })
+    }
+
+    /// Requests received since the last call.
+    pub fn calls(&self) -> impl Iterator<Item = IpcCall> + '_ {
+        self.calls.try_iter()
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // If another server took the endpoint over meanwhile, its file stays. Our accept thread
+        // can't be woken then and only ends with the process.
+        if self.endpoint_id.is_none() || transport::endpoint_id(&self.endpoint) != self.endpoint_id
+        {
+            return;
+        }
+        // Wake the accept thread so it sees the stop flag.
+        let _ = transport::connect(&self.endpoint);
+        let _ = std::fs::remove_file(&self.endpoint);
+    }
+}
+
+fn serve_connection(stream: transport::Stream, calls: Sender<IpcCall>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+
+    match read_message(&mut reader) {
+        Ok(Some(IpcRequest::Hello { version })) if version == IPC_VERSION => {
+            let hello = IpcResponse::Hello {
+                version: IPC_VERSION,
+            };
+            if send_message(&mut writer, &hello).is_err() {
+                return;
+            }
+        }
+        Ok(Some(IpcRequest::Hello { version })) => {
+            let message = format!("Unsupported IPC version {version}, expected {IPC_VERSION}");
+            let _ = send_message(&mut writer, &IpcResponse::Error { message });
+            return;
+        }
+        Ok(None) => return,
+        Ok(Some(_)) | Err(_) => {
+            let message = "Expected hello first".to_string();
+            let _ = send_message(&mut writer, &IpcResponse::Error { message });
+            return;
+        }
+    }
+
+    loop {
+        let response = match read_message::<IpcRequest>(&mut reader) {
+            Ok(None) => return,
+            Ok(Some(request)) => match validate(&request) {
+                Ok(()) => {
+                    let (reply, response) = mpsc::channel();
+                    if calls.send(IpcCall { request, reply }).is_err() {
+                        return;
+                    }
+                    match response.recv_timeout(REPLY_TIMEOUT) {
+                        Ok(response) => response,
+                        Err(RecvTimeoutError::Timeout) => IpcResponse::Error {
+                            message: "The plugin did not answer".to_string(),
+                        },
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                Err(message) => IpcResponse::Error { message },
+            },
+            Err(message) => IpcResponse::Error { message },
+        };
+        if send_message(&mut writer, &response).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reject invalid profiles and patterns before they reach the plugin.
+fn validate(request: &IpcRequest) -> Result<(), String> {
+    match request {
+        IpcRequest::Hello { .. } => Err("Already said hello".to_string()),
+        IpcRequest::SetProfile { profile } => profile.validate(),
+        IpcRequest::PlayPattern { pattern } => pattern.validate().map_err(|e| e.to_string()),
+        IpcRequest::Status
+        | IpcRequest::Intensity
+        | IpcRequest::StopPattern
+        | IpcRequest::SetBacklight { .. } => Ok(()),
+    }
+}
+
+/// A connection to the plugin, after a successful version handshake.
+pub struct IpcClient {
+    reader: BufReader<transport::Stream>,
+    writer: transport::Stream,
+}
+
+impl IpcClient {
+    /// Connect to a plugin serving `endpoint`. Fails if none is running or it speaks another
+    /// protocol version.
+    pub fn connect(endpoint: &Path) -> Result<Self, String> {
+        let stream = transport::connect(endpoint)
+            .map_err(|e| format!("Failed to connect to the plugin: {e}"))?;
+        Self::handshake(stream)
+    }
+
+    fn handshake(stream: transport::Stream) -> Result<Self, String> {
+        stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .map_err(|e| format!("Failed to set the read timeout: {e}"))?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| format!("Failed to connect to the plugin: {e}"))?;
+        let mut client = Self {
+            reader: BufReader::new(stream),
+            writer,
+        };
+        match client.request(&IpcRequest::Hello {
+            version: IPC_VERSION,
+        })? {
+            IpcResponse::Hello { version } if version == IPC_VERSION => Ok(client),
+            IpcResponse::Hello { version } => Err(format!(
+                "The plugin speaks IPC version {version}, expected {IPC_VERSION}"
+            )),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    /// Send a request and wait for its response. An error response becomes `Err`.
+    pub fn request(&mut self, request: &IpcRequest) -> Result<IpcResponse, String> {
+        send_message(&mut self.writer, request)?;
+        match read_message(&mut self.reader)? {
+            Some(IpcResponse::Error { message }) => Err(message),
+            Some(response) => Ok(response),
+            None => Err("The plugin closed the connection".to_string()),
+        }
+    }
+
+    pub fn status(&mut self) -> Result<PluginStatus, String> {
+        match self.request(&IpcRequest::Status)? {
+            IpcResponse::Status { status } => Ok(status),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    pub fn intensity(&mut self) -> Result<u8, String> {
+        match self.request(&IpcRequest::Intensity)? {
+            IpcResponse::Intensity { intensity } => Ok(intensity),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    pub fn set_profile(&mut self, profile: Profile) -> Result<(), String> {
+        self.expect_ok(&IpcRequest::SetProfile { profile })
+    }
+
+    pub fn play_pattern(&mut self, pattern: Pattern) -> Result<(), String> {
+        self.expect_ok(&IpcRequest::PlayPattern { pattern })
+    }
+
+    pub fn stop_pattern(&mut self) -> Result<(), String> {
+        self.expect_ok(&IpcRequest::StopPattern)
+    }
+
+    pub fn set_backlight(&mut self, level: u8) -> Result<(), String> {
+        self.expect_ok(&IpcRequest::SetBacklight { level })
+    }
+
+    fn expect_ok(&mut self, request: &IpcRequest) -> Result<(), String> {
+        match self.request(request)? {
+            IpcResponse::Ok => Ok(()),
+            response => Err(unexpected(&response)),
+        }
+    }
+}
+
+/// Who writes to the device.
+pub enum DeviceOwner {
+    /// No plugin serves the endpoint, so the device is free to open.
+    Nobody,
+    /// A plugin owns the device; commands for it go through this connection.
+    Plugin(IpcClient),
+    /// A plugin owns the device but can't take commands, e.g. it speaks another protocol
+    /// version. The device must be left alone.
+    Unreachable(String),
+}
+
+/// Find out who owns the device: the plugin serving `endpoint`, if any.
+pub fn device_owner(endpoint: &Path) -> DeviceOwner {
+    let Ok(stream) = transport::connect(endpoint) else {
+        return DeviceOwner::Nobody;
+    };
+    match IpcClient::handshake(stream) {
+        Ok(client) => DeviceOwner::Plugin(client),
+        Err(e) => DeviceOwner::Unreachable(format!("{DEVICE_IN_USE}: {e}")),
+    }
+}
+
+/// For tools that write to the stick themselves: an error while a plugin owns the device.
+pub fn ensure_device_free(endpoint: &Path) -> Result<(), String> {
+    match device_owner(endpoint) {
+        DeviceOwner::Nobody => Ok(()),
+        DeviceOwner::Plugin(_) => Err(format!(
+            "{DEVICE_IN_USE}; disable the plugin or quit X-Plane first"
+        )),
+        DeviceOwner::Unreachable(e) => Err(e),
+    }
+}
+
+fn unexpected(response: &IpcResponse) -> String {
+    format!("Unexpected response from the plugin: {response:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("xa-ursa-minor-test-{}-{name}", std::process::id()))
+    }
+
+    /// Answer calls like the plugin would, until the server is dropped.
+    fn answer(server: IpcServer) -> thread::JoinHandle<Vec<IpcRequest>> {
+        thread::spawn(move || {
+            let mut handled = Vec::new();
+            while handled.len() < 4 {
+                for call in server.calls() {
+                    let response = match &call.request {
+                        IpcRequest::Status => IpcResponse::Status {
+                            status: PluginStatus {
+                                profile: "Default".to_string(),
+                                ..Default::default()
+                            },
+                        },
+                        IpcRequest::Intensity => IpcResponse::Intensity { intensity: 42 },
+                        _ => IpcResponse::Ok,
+                    };
+                    handled.push(call.request.clone());
+                    call.reply(response);
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            handled
+        })
+    }
+
+    #[test]
+    fn test_requests() {
+        let endpoint = endpoint("requests");
+        let answering = answer(IpcServer::start(&endpoint, |_| {}).unwrap());
+
+        let mut client = IpcClient::connect(&endpoint).unwrap();
+        assert_eq!(client.status().unwrap().profile, "Default");
+        assert_eq!(client.intensity().unwrap(), 42);
+        client.set_profile(Profile::default()).unwrap();
+        let mut invalid = Profile::default();
+        invalid.gforce.duration = -1.0;
+        assert!(client.set_profile(invalid).is_err());
+        client.stop_pattern().unwrap();
+
+        let handled = answering.join().unwrap();
+        assert_eq!(handled.len(), 4);
+        assert_eq!(handled[3], IpcRequest::StopPattern);
+        assert!(!endpoint.exists());
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let endpoint = endpoint("version");
+        let _server = IpcServer::start(&endpoint, |_| {}).unwrap();
+
+        let stream = transport::connect(&endpoint).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        send_message(&mut writer, &IpcRequest::Hello { version: 99 }).unwrap();
+        let response: Option<IpcResponse> = read_message(&mut reader).unwrap();
+        assert!(matches!(response, Some(IpcResponse::Error { message }) if message.contains("99")));
+        assert_eq!(read_message::<IpcResponse>(&mut reader), Ok(None));
+    }
+
+    #[test]
+    fn test_endpoint_is_not_stolen() {
+        let endpoint = endpoint("taken");
+        let first = IpcServer::start(&endpoint, |_| {}).unwrap();
+        assert!(IpcServer::start(&endpoint, |_| {}).is_err());
+        assert!(matches!(device_owner(&endpoint), DeviceOwner::Plugin(_)));
+
+        // A newer server that took over the endpoint keeps it when the old one goes away.
+        std::fs::remove_file(&endpoint).unwrap();
+        let _second = IpcServer::start(&endpoint, |_| {}).unwrap();
+        drop(first);
+        assert!(endpoint.exists());
+        assert!(matches!(device_owner(&endpoint), DeviceOwner::Plugin(_)));
+    }
+
+    #[test]
+    fn test_stale_endpoint_is_replaced() {
+        let endpoint = endpoint("stale");
+        std::fs::write(&endpoint, "").unwrap();
+        let server = IpcServer::start(&endpoint, |_| {}).unwrap();
+        assert!(matches!(device_owner(&endpoint), DeviceOwner::Plugin(_)));
+        assert!(ensure_device_free(&endpoint).is_err());
+        drop(server);
+        assert!(!endpoint.exists());
+        assert_eq!(ensure_device_free(&endpoint), Ok(()));
+    }
+
+    #[test]
+    fn test_no_plugin() {
+        assert!(IpcClient::connect(&endpoint("missing")).is_err());
+        assert!(matches!(
+            device_owner(&endpoint("missing")),
+            DeviceOwner::Nobody
+        ));
+    }
+
+    #[test]
+    fn test_device_owner() {
+        let endpoint = endpoint("owner");
+        let server = IpcServer::start(&endpoint, |_| {}).unwrap();
+        let DeviceOwner::Plugin(mut client) = device_owner(&endpoint) else {
+            panic!("the plugin should own the device");
+        };
+        let answering = thread::spawn(move || loop {
+            if let Some(call) = server.calls().next() {
+                assert_eq!(call.request, IpcRequest::SetBacklight { level: 128 });
+                call.reply(IpcResponse::Ok);
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        });
+        client.set_backlight(128).unwrap();
+        answering.join().unwrap();
+    }
+}
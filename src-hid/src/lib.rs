@@ -1 +1,15 @@
+pub mod engine;
+pub mod external;
+pub mod filter;
 pub mod hid;
+pub mod ipc;
+pub mod pattern;
+pub mod profile;
+pub mod replay;
+pub mod ring;
+pub mod sample;
+pub mod telemetry;
+pub mod udp;
+mod validate;
+pub mod waveform;
+pub mod writer;
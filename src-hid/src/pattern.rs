@@ -0,0 +1,290 @@
+use crate::validate::check_positive;
+use crate::waveform::Waveform;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// One step of a haptic pattern.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    /// Peak intensity of the segment, 0..1.
+    pub intensity: f32,
+    /// Duration of one pass through the segment, in seconds.
+    pub duration: f32,
+    /// Shape of the segment. Without a curve the segment holds `intensity` flat.
+    #[serde(default)]
+    pub curve: Option<Waveform>,
+    /// Cycles of the curve per pass, for periodic curves.
+    #[serde(default = "default_frequency")]
+    pub frequency: f32,
+    /// How many times the segment plays before moving on.
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+}
+
+/// A haptic pattern authored as data: a sequence of segments, optionally repeated as a whole.
+/// ```json
+/// {
+///   "name": "Double thump",
+///   "segments": [
+///     { "intensity": 1.0, "duration": 0.12, "curve": { "type": "half_sine" }, "repeat": 2 },
+///     { "intensity": 0.4, "duration": 0.3 }
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub name: String,
+    pub segments: Vec<Segment>,
+    /// How many times the whole sequence plays.
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    /// Optional dataref that fires the pattern in the plugin when it goes from zero to non-zero.
+    #[serde(default)]
+    pub trigger: Option<String>,
+}
+
+/// Longest a pattern may play, in seconds, repeats included. Patterns are short effects; this
+/// keeps a typo in a duration or repeat count from holding the motor for hours.
+pub const MAX_PATTERN_DURATION: f32 = 600.0;
+
+fn default_frequency() -> f32 {
+    1.0
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+/// Why a pattern could not be loaded.
+#[derive(Debug, PartialEq)]
+pub enum PatternError {
+    /// The file could not be read.
+    Io(String),
+    /// The JSON is malformed or doesn't match the format.
+    Parse(String),
+    /// The pattern has no segments.
+    Empty,
+    /// The whole-pattern repeat count is zero.
+    ZeroRepeat,
+    /// A segment (0-based index) has an invalid value.
+    Segment { index: usize, message: String },
+    /// Segment durations times the repeat counts add up to more than `MAX_PATTERN_DURATION`.
+    TooLong(f32),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::Io(e) => write!(f, "Failed to read pattern: {e}"),
+            PatternError::Parse(e) => write!(f, "Invalid pattern JSON: {e}"),
+            PatternError::Empty => write!(f, "Pattern has no segments"),
+            PatternError::ZeroRepeat => write!(f, "Pattern repeat count must be at least 1"),
+            PatternError::Segment { index, message } => write!(f, "Segment {index}: {message}"),
+            PatternError::TooLong(duration) => write!(
+                f,
+                "Pattern plays for {duration} s, longer than {MAX_PATTERN_DURATION} s"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl Segment {
+    fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.intensity) {
+            return Err(format!(
+                "intensity must be within 0..1, got {}",
+                self.intensity
+            ));
+        }
+        check_positive("duration", self.duration)?;
+        check_positive("frequency", self.frequency)?;
+        if self.repeat == 0 {
+            return Err("repeat count must be at least 1".to_string());
+        }
+        match &self.curve {
+            Some(curve) => curve.validate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Level (0..1) at `elapsed` seconds into one pass of the segment.
+    fn level_at(&self, elapsed: f32) -> f32 {
+        let shaped = match &self.curve {
+            Some(curve) => curve.sample(elapsed / self.duration, self.frequency, 1.0, 0),
+            None => 1.0,
+        };
+        shaped * self.intensity
+    }
+}
+
+impl Pattern {
+    /// Parse and validate a pattern from JSON.
+    pub fn from_json(json: &str) -> Result<Self, PatternError> {
+        let pattern: Pattern =
+            serde_json::from_str(json).map_err(|e| PatternError::Parse(e.to_string()))?;
+        pattern.validate()?;
+        Ok(pattern)
+    }
+
+    /// Load a pattern from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, PatternError> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| PatternError::Io(format!("{}: {e}", path.display())))?;
+        Self::from_json(&json)
+    }
+
+    pub fn validate(&self) -> Result<(), PatternError> {
+        if self.segments.is_empty() {
+            return Err(PatternError::Empty);
+        }
+        if self.repeat == 0 {
+            return Err(PatternError::ZeroRepeat);
+        }
+        for (index, segment) in self.segments.iter().enumerate() {
+            segment
+                .validate()
+                .map_err(|message| PatternError::Segment { index, message })?;
+        }
+        // Finite segments can still multiply up to infinity through the repeat counts.
+        let total = self.total_duration();
+        if !total.is_finite() || total > MAX_PATTERN_DURATION {
+            return Err(PatternError::TooLong(total));
+        }
+        Ok(())
+    }
+
+    /// Duration of one pass through all segments, in seconds.
+    fn pass_duration(&self) -> f32 {
+        self.segments
+            .iter()
+            .map(|segment| segment.duration * segment.repeat as f32)
+            .sum()
+    }
+
+    /// Total play time including all repeats, in seconds.
+    pub fn total_duration(&self) -> f32 {
+        self.pass_duration() * self.repeat as f32
+    }
+
+    /// Motor intensity (0..255) at `elapsed` seconds since the pattern started,
+    /// or `None` once the pattern has finished.
+    pub fn intensity_at(&self, elapsed: f32) -> Option<u8> {
+        if elapsed < 0.0 || elapsed >= self.total_duration() {
+            return None;
+        }
+
+        // Position inside the current pass, then walk the segments to find ours.
+        let mut remaining = elapsed % self.pass_duration();
+        for segment in &self.segments {
+            let span = segment.duration * segment.repeat as f32;
+            if remaining < span {
+                let level = segment.level_at(remaining % segment.duration);
+                return Some((level * 255.0).round().clamp(0.0, 255.0) as u8);
+            }
+            remaining -= span;
+        }
+
+        // Only reachable through float rounding at the very end of a pass.
+        Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOUBLE_THUMP: &str = r#"{
+        "name": "Double thump",
+        "repeat": 2,
+        "segments": [
+            { "intensity": 1.0, "duration": 0.1, "curve": { "type": "half_sine" }, "repeat": 2 },
+            { "intensity": 0.5, "duration": 0.3 }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_pattern() {
+        let pattern = Pattern::from_json(DOUBLE_THUMP).unwrap();
+        assert_eq!(pattern.segments.len(), 2);
+        assert_eq!(pattern.segments[1].repeat, 1);
+        assert!((pattern.total_duration() - 1.0).abs() < 1e-6);
+        assert_eq!(pattern.trigger, None);
+    }
+
+    #[test]
+    fn test_intensity_follows_segments() {
+        let pattern = Pattern::from_json(DOUBLE_THUMP).unwrap();
+        // Peak of the first and second half-sine
+        assert_eq!(pattern.intensity_at(0.05), Some(255));
+        assert_eq!(pattern.intensity_at(0.15), Some(255));
+        // Flat segment
+        assert_eq!(pattern.intensity_at(0.3), Some(128));
+        // Second pass starts over
+        assert_eq!(pattern.intensity_at(0.55), Some(255));
+        assert_eq!(pattern.intensity_at(1.0), None);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(matches!(
+            Pattern::from_json("{ \"name\": \"x\" }"),
+            Err(PatternError::Parse(_))
+        ));
+        assert_eq!(
+            Pattern::from_json(r#"{ "name": "x", "segments": [] }"#),
+            Err(PatternError::Empty)
+        );
+        let bad_segment = r#"{ "name": "x", "segments": [
+            { "intensity": 0.5, "duration": 0.1 },
+            { "intensity": 1.5, "duration": 0.1 }
+        ] }"#;
+        assert!(matches!(
+            Pattern::from_json(bad_segment),
+            Err(PatternError::Segment { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_non_finite_values_are_rejected() {
+        type Set = fn(&mut Segment, f32);
+        let fields: [(&str, Set); 4] = [
+            ("intensity", |segment, value| segment.intensity = value),
+            ("duration", |segment, value| segment.duration = value),
+            ("frequency", |segment, value| segment.frequency = value),
+            ("curve", |segment, value| {
+                segment.curve = Some(Waveform::SquarePulse { duty: value })
+            }),
+        ];
+        for (field, set) in fields {
+            for value in [f32::NAN, f32::INFINITY] {
+                let mut pattern = Pattern::from_json(DOUBLE_THUMP).unwrap();
+                set(&mut pattern.segments[0], value);
+                assert!(
+                    matches!(
+                        pattern.validate(),
+                        Err(PatternError::Segment { index: 0, .. })
+                    ),
+                    "{field} = {value}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_total_duration_is_capped() {
+        // Every value is finite, but the repeats multiply past f32::MAX.
+        let endless = r#"{ "name": "x", "repeat": 10,
+            "segments": [{ "intensity": 0.5, "duration": 1e38, "repeat": 10 }] }"#;
+        assert!(matches!(
+            Pattern::from_json(endless),
+            Err(PatternError::TooLong(_))
+        ));
+        let hour = r#"{ "name": "x", "repeat": 60,
+            "segments": [{ "intensity": 0.5, "duration": 60.0 }] }"#;
+        assert_eq!(Pattern::from_json(hour), Err(PatternError::TooLong(3600.0)));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::filter::FilterConfig;
+use crate::validate::check_positive;
+use crate::waveform::Waveform;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// X-Plane's stall warning, which drives the stick shaker unless a profile says otherwise.
+pub const DEFAULT_SHAKER_DATAREF: &str = "sim/cockpit2/annunciators/stall_warning";
+
+/// Waveform and per-wave duration used by one effect.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EffectProfile {
+    pub waveform: Waveform,
+    /// Duration of each wave in seconds.
+    pub duration: f32,
+}
+
+impl EffectProfile {
+    pub fn validate(&self, effect: &str) -> Result<(), String> {
+        check_positive("duration", self.duration).map_err(|e| format!("{effect}: {e}"))?;
+        self.waveform
+            .validate()
+            .map_err(|e| format!("{effect}: {e}"))
+    }
+}
+
+/// What the g-force effect reacts to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputMode {
+    /// The g-force itself. The high-pass filter still removes steady loads, so this follows
+    /// slower changes (long pull-ups, turns) more than the other modes.
+    RawG,
+    /// Change of g-force per frame, normalized to the reference frame period.
+    #[default]
+    DeltaG,
+    /// Change of the per-frame g-force change: only abrupt onsets (touchdown, turbulence bumps)
+    /// come through, while smooth manoeuvres stay quiet.
+    Jerk,
+}
+
+/// How g-force samples are turned into the input of the g-force effect.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputProfile {
+    pub mode: InputMode,
+    /// (side, axial, normal) weights applied to the input before filtering. 0 ignores an axis.
+    pub weights: (f32, f32, f32),
+}
+
+impl Default for InputProfile {
+    fn default() -> Self {
+        Self {
+            mode: InputMode::DeltaG,
+            weights: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl InputProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        let (x, y, z) = self.weights;
+        if ![x, y, z]
+            .iter()
+            .all(|weight| weight.is_finite() && *weight >= 0.0)
+        {
+            return Err(format!(
+                "input: axis weights must be finite and not negative, got ({x}, {y}, {z})"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A vibration profile: which waveform each effect uses and how long its waves last.
+/// Missing fields in a profile file fall back to the defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    /// Waves spawned from g-force changes.
+    pub gforce: EffectProfile,
+    /// One stick shaker pulse. The shaker repeats it for as long as a shaker dataref is on.
+    pub shaker: EffectProfile,
+    /// Datarefs (int or float) that turn the stick shaker on while non-zero. Aircraft that drive
+    /// their own shaker list theirs here; names the loaded aircraft doesn't have are skipped.
+    pub shaker_datarefs: Vec<String>,
+    /// What feeds the g-force effect.
+    pub input: InputProfile,
+    /// Per-axis cutoffs applied to the weighted input.
+    pub filter: FilterConfig,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            name: "Default".to_string(),
+            gforce: EffectProfile {
+                waveform: Waveform::ClippedSine,
+                duration: 0.2,
+            },
+            shaker: EffectProfile {
+                waveform: Waveform::SquarePulse { duty: 0.6 },
+                duration: 0.125,
+            },
+            shaker_datarefs: vec![DEFAULT_SHAKER_DATAREF.to_string()],
+            input: InputProfile::default(),
+            filter: FilterConfig::default(),
+        }
+    }
+}
+
+impl Profile {
+    /// Parse and validate a profile from JSON.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let profile: Profile =
+            serde_json::from_str(json).map_err(|e| format!("Invalid profile JSON: {e}"))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Load a profile from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    /// Save the profile as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize profile: {e}"))?;
+        fs::write(path, json).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.gforce.validate("gforce")?;
+        self.shaker.validate("shaker")?;
+        self.input.validate()?;
+        self.filter.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_profile_uses_defaults() {
+        let json = r#"{
+            "name": "Bumpy",
+            "gforce": {
+                "waveform": { "type": "keyframes", "points": [[0.0, 0.0], [0.1, 1.0], [1.0, 0.0]] },
+                "duration": 0.3
+            }
+        }"#;
+        let profile = Profile::from_json(json).unwrap();
+        assert_eq!(profile.name, "Bumpy");
+        assert_eq!(profile.gforce.duration, 0.3);
+        assert_eq!(profile.shaker, Profile::default().shaker);
+        assert_eq!(profile.shaker_datarefs, vec![DEFAULT_SHAKER_DATAREF]);
+        assert_eq!(profile.input, InputProfile::default());
+    }
+
+    #[test]
+    fn test_input_profile() {
+        let json = r#"{ "input": { "mode": "jerk", "weights": [0.5, 0.0, 1.0] } }"#;
+        let profile = Profile::from_json(json).unwrap();
+        assert_eq!(profile.input.mode, InputMode::Jerk);
+        assert_eq!(profile.input.weights, (0.5, 0.0, 1.0));
+
+        let negative = r#"{ "input": { "weights": [1.0, -1.0, 1.0] } }"#;
+        assert!(Profile::from_json(negative).is_err());
+    }
+
+    #[test]
+    fn test_filter_profile() {
+        let json = r#"{ "filter": { "normal": { "high_pass_hz": 0.5, "low_pass_hz": 3.0 } } }"#;
+        let profile = Profile::from_json(json).unwrap();
+        assert_eq!(profile.filter.normal.low_pass_hz, Some(3.0));
+        assert_eq!(profile.filter.side, Default::default());
+
+        let inverted = r#"{ "filter": { "side": { "high_pass_hz": 4.0, "low_pass_hz": 3.0 } } }"#;
+        assert!(Profile::from_json(inverted).is_err());
+    }
+
+    #[test]
+    fn test_invalid_profile_is_rejected() {
+        let json = r#"{ "gforce": { "waveform": { "type": "half_sine" }, "duration": 0.0 } }"#;
+        assert!(Profile::from_json(json).is_err());
+    }
+
+    #[test]
+    fn test_non_finite_values_are_rejected() {
+        type Set = fn(&mut Profile, f32);
+        let fields: [(&str, Set); 4] = [
+            ("gforce duration", |profile, value| {
+                profile.gforce.duration = value
+            }),
+            ("input weight", |profile, value| {
+                profile.input.weights.1 = value
+            }),
+            ("side high pass", |profile, value| {
+                profile.filter.side.high_pass_hz = Some(value)
+            }),
+            ("shaker duty", |profile, value| {
+                profile.shaker.waveform = Waveform::SquarePulse { duty: value }
+            }),
+        ];
+        for (field, set) in fields {
+            for value in [f32::NAN, f32::INFINITY] {
+                let mut profile = Profile::default();
+                set(&mut profile, value);
+                assert!(profile.validate().is_err(), "{field} = {value}");
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::engine::{EngineParams, VibrationEngine};
+use crate::profile::Profile;
+use crate::telemetry::TelemetryRecord;
+use serde::{Deserialize, Serialize};
+
+/// How often the plugin's vibration worker updates the engine, in seconds.
+pub const DEFAULT_INTERVAL: f64 = 0.02;
+
+/// Seconds the engine keeps running after the last sample, so the last waves can finish.
+const TAIL: f64 = 1.0;
+
+/// A change of motor intensity, `time` seconds into the recording.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutputEvent {
+    pub time: f64,
+    pub intensity: u8,
+}
+
+/// Run the samples of a recording through a fresh engine, updating it every `interval` seconds
+/// like the plugin's worker does, and return every change of the motor intensity.
+///
+/// Only the g-force effect is replayed: stick shaker, patterns and overrides are commands, which
+/// aren't recorded.
+pub fn replay(
+    records: &[TelemetryRecord],
+    profile: Profile,
+    params: EngineParams,
+    interval: f64,
+) -> Vec<OutputEvent> {
+    let mut samples = records
+        .iter()
+        .filter_map(|record| match record {
+            TelemetryRecord::Sample(record) => Some(record),
+            TelemetryRecord::Output { .. } => None,
+        })
+        .peekable();
+    let Some(start) = samples.peek().map(|record| record.time) else {
+        return Vec::new();
+    };
+    let end = samples.clone().last().map_or(start, |record| record.time) + TAIL;
+
+    let mut engine = VibrationEngine::new(profile, params);
+    let mut timeline = Vec::new();
+    let mut last_intensity = 0;
+    for step in 0.. {
+        let now = start + step as f64 * interval;
+        if now > end {
+            break;
+        }
+        // Like the worker, take in every sample that arrived since the last update first.
+        while let Some(record) = samples.next_if(|record| record.time <= now) {
+            engine.push_sample(&record.sample, record.time);
+        }
+        let intensity = engine.update(now);
+        if intensity != last_intensity {
+            timeline.push(OutputEvent {
+                time: now - start,
+                intensity,
+            });
+            last_intensity = intensity;
+        }
+    }
+    timeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::REFERENCE_DT;
+    use crate::sample::SimSample;
+    use crate::telemetry::SampleRecord;
+
+    fn records(normal_g: &[f32]) -> Vec<TelemetryRecord> {
+        normal_g
+            .iter()
+            .enumerate()
+            .map(|(frame, &g)| {
+                let time = frame as f64 * REFERENCE_DT as f64;
+                TelemetryRecord::Sample(SampleRecord {
+                    time,
+                    sample: SimSample {
+                        sim_time: time,
+                        dt: REFERENCE_DT,
+                        g_force: (0.0, 0.0, g),
+                        ..Default::default()
+                    },
+                    delta: None,
+                    filtered: None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_touchdown_vibrates_and_settles() {
+        let timeline = replay(
+            &records(&[1.0, 1.0, 1.0, 2.5, 1.2, 1.0, 1.0]),
+            Profile::default(),
+            EngineParams::default(),
+            DEFAULT_INTERVAL,
+        );
+        assert!(timeline.iter().any(|event| event.intensity > 0));
+        assert_eq!(timeline.last().unwrap().intensity, 0);
+        assert!(timeline.windows(2).all(|pair| pair[0].time < pair[1].time));
+    }
+
+    #[test]
+    fn test_steady_flight_is_quiet() {
+        let timeline = replay(
+            &records(&[1.0; 30]),
+            Profile::default(),
+            EngineParams::default(),
+            DEFAULT_INTERVAL,
+        );
+        assert!(timeline.is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::TryRecvError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A bounded single-producer, single-consumer channel that never blocks the sender: when it's
+/// full, the oldest item is dropped to make room. Items that waited longer than the maximum age
+/// are discarded by the receiver instead of being handed out late.
+///
+/// The receiver can sleep until an item arrives (see `RingReceiver::wait`), and other threads can
+/// wake it through a `RingWaker`, e.g. when they sent it a message some other way.
+pub fn ring_channel<T>(capacity: usize, max_age: Duration) -> (RingSender<T>, RingReceiver<T>) {
+    assert!(capacity > 0, "a ring channel needs room for one item");
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner {
+            queue: VecDeque::with_capacity(capacity),
+            woken: false,
+        }),
+        changed: Condvar::new(),
+        capacity,
+        max_age,
+        dropped: AtomicU64::new(0),
+        stale: AtomicU64::new(0),
+        sender_alive: AtomicBool::new(true),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        RingSender {
+            shared: Arc::clone(&shared),
+        },
+        RingReceiver { shared },
+    )
+}
+
+struct Inner<T> {
+    queue: VecDeque<(Instant, T)>,
+    /// Set by `RingWaker::wake` until the receiver returns from `wait`.
+    woken: bool,
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    /// Signalled on every send, wake and disconnect.
+    changed: Condvar,
+    capacity: usize,
+    max_age: Duration,
+    /// Items pushed out by newer ones while the channel was full.
+    dropped: AtomicU64,
+    /// Items discarded for being older than `max_age`.
+    stale: AtomicU64,
+    sender_alive: AtomicBool,
+    receiver_alive: AtomicBool,
+}
+
+impl<T> Shared<T> {
+    fn notify(&self) {
+        // Taking the lock orders this after a receiver that is about to wait.
+        drop(self.inner.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    fn counters(&self) -> RingCounters {
+        RingCounters {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Items lost so far, and why.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RingCounters {
+    pub dropped: u64,
+    pub stale: u64,
+}
+
+/// The receiving half is gone; carries the item that couldn't be sent.
+#[derive(Debug, PartialEq)]
+pub struct Disconnected<T>(pub T);
+
+pub struct RingSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> RingSender<T> {
+    /// Queue an item, dropping the oldest one if the channel is full.
+    pub fn send(&self, item: T) -> Result<(), Disconnected<T>> {
+        if !self.shared.receiver_alive.load(Ordering::Relaxed) {
+            return Err(Disconnected(item));
+        }
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.queue.len() == self.shared.capacity {
+            inner.queue.pop_front();
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        inner.queue.push_back((Instant::now(), item));
+        drop(inner);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    pub fn counters(&self) -> RingCounters {
+        self.shared.counters()
+    }
+}
+
+impl<T> Drop for RingSender<T> {
+    fn drop(&mut self) {
+        self.shared.sender_alive.store(false, Ordering::Relaxed);
+        self.shared.notify();
+    }
+}
+
+pub struct RingReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> RingReceiver<T> {
+    /// Take the oldest item that isn't stale. Like `mpsc::Receiver::try_recv`, reports
+    /// `Disconnected` only once the sender is gone and the channel is empty.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        while let Some((queued, item)) = inner.queue.pop_front() {
+            if queued.elapsed() <= self.shared.max_age {
+                return Ok(item);
+            }
+            self.shared.stale.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.shared.sender_alive.load(Ordering::Relaxed) {
+            Err(TryRecvError::Empty)
+        } else {
+            Err(TryRecvError::Disconnected)
+        }
+    }
+
+    /// Sleep until an item is queued, the receiver is woken, the sender is gone or `deadline`
+    /// passes, whichever comes first. Without a deadline it only returns for the others.
+    pub fn wait(&self, deadline: Option<Instant>) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        loop {
+            if !inner.queue.is_empty()
+                || std::mem::take(&mut inner.woken)
+                || !self.shared.sender_alive.load(Ordering::Relaxed)
+            {
+                return;
+            }
+            match deadline {
+                Some(deadline) => {
+                    let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                        return;
+                    };
+                    inner = self.shared.changed.wait_timeout(inner, timeout).unwrap().0;
+                }
+                None => inner = self.shared.changed.wait(inner).unwrap(),
+            }
+        }
+    }
+
+    /// A handle that makes `wait` return, usable from any thread.
+    pub fn waker(&self) -> RingWaker<T> {
+        RingWaker {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    pub fn counters(&self) -> RingCounters {
+        self.shared.counters()
+    }
+}
+
+/// Wakes the receiver of a ring channel.
+pub struct RingWaker<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Not derived: that would require `T: Clone`.
+impl<T> Clone for RingWaker<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> RingWaker<T> {
+    /// Make the receiver's current or next `wait` return.
+    pub fn wake(&self) {
+        self.shared.inner.lock().unwrap().woken = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl<T> Drop for RingReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_full_channel_drops_oldest() {
+        let (tx, rx) = ring_channel(3, Duration::from_secs(60));
+        for item in 1..=5 {
+            tx.send(item).unwrap();
+        }
+        let received: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(received, vec![3, 4, 5]);
+        assert_eq!(
+            rx.counters(),
+            RingCounters {
+                dropped: 2,
+                stale: 0
+            }
+        );
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_stale_items_are_discarded() {
+        let (tx, rx) = ring_channel(10, Duration::from_millis(20));
+        tx.send("old").unwrap();
+        tx.send("older").unwrap();
+        thread::sleep(Duration::from_millis(40));
+        tx.send("fresh").unwrap();
+        assert_eq!(rx.try_recv(), Ok("fresh"));
+        assert_eq!(tx.counters().stale, 2);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (tx, rx) = ring_channel(2, Duration::from_secs(60));
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = ring_channel(2, Duration::from_secs(60));
+        drop(rx);
+        assert_eq!(tx.send(7), Err(Disconnected(7)));
+    }
+
+    #[test]
+    fn test_wait() {
+        let (tx, rx) = ring_channel(2, Duration::from_secs(60));
+
+        let started = Instant::now();
+        rx.wait(Some(started + Duration::from_millis(20)));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        let waker = rx.waker();
+        let waking = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            waker.wake();
+            thread::sleep(Duration::from_millis(10));
+            tx.send(1).unwrap();
+        });
+        // Woken, then an item, then the sender is gone: each ends one wait.
+        rx.wait(None);
+        rx.wait(None);
+        assert_eq!(rx.try_recv(), Ok(1));
+        waking.join().unwrap();
+        rx.wait(None);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
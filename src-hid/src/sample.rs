@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+/// One reading of the sim state that drives vibration effects, taken by the flight loop (or
+/// replayed from telemetry) and handed to the vibration engine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SimSample {
+    /// Sim time of the reading, in seconds since the sim started.
+    pub sim_time: f64,
+    /// Seconds since the previous reading.
+    pub dt: f32,
+    /// (side, axial, normal) g-force.
+    pub g_force: (f32, f32, f32),
+    /// Any wheel on the ground.
+    pub on_ground: bool,
+    /// Ground speed, in m/s.
+    pub groundspeed: f32,
+    /// Indicated airspeed, in knots.
+    pub indicated_airspeed: f32,
+    /// Angle of attack, in degrees.
+    pub angle_of_attack: f32,
+    /// Gear extension, 0 (up) to 1 (down). The most extended gear leg counts.
+    pub gear_deploy: f32,
+    /// Flap extension, 0 (up) to 1 (fully down).
+    pub flap_deploy: f32,
+    /// The sim is paused.
+    pub paused: bool,
+    /// The sim is playing back a replay.
+    pub replay: bool,
+    /// The sim view is replaced by one of the sim's full-screen menus. Missing from older
+    /// telemetry, which reads as false.
+    #[serde(default)]
+    pub in_menu: bool,
+}
+
+impl SimSample {
+    /// The sim is flying live: not paused, not playing back a replay and not in a menu.
+    pub fn is_live(&self) -> bool {
+        !self.paused && !self.replay && !self.in_menu
+    }
+
+    /// Change of g-force since `previous`, per axis.
+    pub fn g_force_delta(&self, previous: &SimSample) -> (f32, f32, f32) {
+        let (x, y, z) = self.g_force;
+        let (px, py, pz) = previous.g_force;
+        (x - px, y - py, z - pz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_g_force_delta() {
+        let previous = SimSample {
+            g_force: (0.0, 0.1, 1.0),
+            ..Default::default()
+        };
+        let sample = SimSample {
+            g_force: (0.5, 0.1, 1.5),
+            ..Default::default()
+        };
+        assert_eq!(sample.g_force_delta(&previous), (0.5, 0.0, 0.5));
+    }
+
+    #[test]
+    fn test_is_live() {
+        assert!(SimSample::default().is_live());
+        let paused = SimSample {
+            paused: true,
+            ..Default::default()
+        };
+        let replay = SimSample {
+            replay: true,
+            ..Default::default()
+        };
+        assert!(!paused.is_live());
+        assert!(!replay.is_live());
+
+        // A menu over a running sim suppresses effects on its own.
+        let in_menu = SimSample {
+            in_menu: true,
+            ..Default::default()
+        };
+        assert!(!in_menu.is_live());
+        assert!(!SimSample {
+            paused: true,
+            in_menu: true,
+            ..Default::default()
+        }
+        .is_live());
+    }
+
+    #[test]
+    fn test_older_samples_are_not_in_menu() {
+        let json = r#"{"sim_time":1.0,"dt":0.02,"g_force":[0.0,0.0,1.0],"on_ground":false,
+            "groundspeed":0.0,"indicated_airspeed":0.0,"angle_of_attack":0.0,"gear_deploy":0.0,
+            "flap_deploy":0.0,"paused":false,"replay":false}"#;
+        let sample: SimSample = serde_json::from_str(json).unwrap();
+        assert!(!sample.in_menu);
+        assert!(sample.is_live());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let sample = SimSample {
+            sim_time: 12.5,
+            dt: 0.02,
+            g_force: (0.0, 0.1, 1.0),
+            on_ground: true,
+            groundspeed: 30.0,
+            paused: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&sample).unwrap();
+        assert_eq!(serde_json::from_str::<SimSample>(&json).unwrap(), sample);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::sample::SimSample;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// First bytes of a binary telemetry file, followed by the little-endian format version.
+pub const MAGIC: &[u8; 8] = b"XAURSATL";
+/// Version of the binary format written by `TelemetryWriter`.
+pub const FORMAT_VERSION: u16 = 1;
+
+const TAG_SAMPLE: u8 = 0;
+const TAG_OUTPUT: u8 = 1;
+
+const FLAG_ON_GROUND: u8 = 1 << 0;
+const FLAG_PAUSED: u8 = 1 << 1;
+const FLAG_REPLAY: u8 = 1 << 2;
+const FLAG_DELTA: u8 = 1 << 3;
+const FLAG_FILTERED: u8 = 1 << 4;
+const FLAG_IN_MENU: u8 = 1 << 5;
+
+const CSV_HEADER: &str = "record,time,sim_time,dt,g_side,g_axial,g_normal,\
+delta_side,delta_axial,delta_normal,filtered_side,filtered_axial,filtered_normal,\
+on_ground,groundspeed,indicated_airspeed,angle_of_attack,gear_deploy,flap_deploy,\
+paused,replay,in_menu,intensity";
+/// Number of CSV columns between `time` and `intensity`, left empty on output rows.
+const CSV_SAMPLE_COLUMNS: usize = 20;
+
+/// A sim sample as the vibration engine saw it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleRecord {
+    /// Seconds since the recording started.
+    pub time: f64,
+    pub sample: SimSample,
+    /// Normalized g-force change since the previous sample. `None` for the first sample after a
+    /// (re)start, pause or replay.
+    pub delta: Option<(f32, f32, f32)>,
+    /// Weighted and filtered input of the g-force effect. `None` when no wave could be derived
+    /// from the sample.
+    pub filtered: Option<(f32, f32, f32)>,
+}
+
+/// One line of a telemetry recording.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TelemetryRecord {
+    Sample(SampleRecord),
+    /// An intensity written to the motor, `time` seconds after the recording started.
+    Output {
+        time: f64,
+        intensity: u8,
+    },
+}
+
+/// Writes telemetry as CSV (for spreadsheets) and as compact binary (for replaying) side by side,
+/// until `max_bytes` have been written across both.
+pub struct TelemetryWriter<W: Write> {
+    csv: W,
+    binary: W,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl TelemetryWriter<BufWriter<File>> {
+    /// Create `<stem>.csv` and `<stem>.bin` in `dir`, creating the directory if needed.
+    /// Returns the writer and the path of the binary file.
+    pub fn create(dir: &Path, stem: &str, max_bytes: u64) -> Result<(Self, PathBuf), String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        let open = |path: &Path| {
+            File::create(path)
+                .map(BufWriter::new)
+                .map_err(|e| format!("Failed to create {}: {e}", path.display()))
+        };
+        let binary_path = dir.join(format!("{stem}.bin"));
+        let csv = open(&dir.join(format!("{stem}.csv")))?;
+        let binary = open(&binary_path)?;
+        Ok((Self::new(csv, binary, max_bytes)?, binary_path))
+    }
+}
+
+impl<W: Write> TelemetryWriter<W> {
+    /// Start a recording by writing the file headers.
+    pub fn new(csv: W, binary: W, max_bytes: u64) -> Result<Self, String> {
+        let mut writer = Self {
+            csv,
+            binary,
+            written: 0,
+            max_bytes,
+        };
+        let csv_header = format!("{CSV_HEADER}\n");
+        let mut binary_header = MAGIC.to_vec();
+        binary_header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        writer.write_both(csv_header.as_bytes(), &binary_header)?;
+        Ok(writer)
+    }
+
+    /// Append a record. Returns `Ok(false)`, without writing, once the record would take the
+    /// recording past its size cap.
+    pub fn write(&mut self, record: &TelemetryRecord) -> Result<bool, String> {
+        let csv = csv_row(record);
+        let binary = encode(record);
+        if self.written + (csv.len() + binary.len()) as u64 > self.max_bytes {
+            return Ok(false);
+        }
+        self.write_both(csv.as_bytes(), &binary)?;
+        Ok(true)
+    }
+
+    /// Bytes written so far, across both files.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Flush both files.
+    pub fn finish(mut self) -> Result<(), String> {
+        self.csv
+            .flush()
+            .and_then(|_| self.binary.flush())
+            .map_err(|e| format!("Failed to flush telemetry: {e}"))
+    }
+
+    fn write_both(&mut self, csv: &[u8], binary: &[u8]) -> Result<(), String> {
+        self.csv
+            .write_all(csv)
+            .and_then(|_| self.binary.write_all(binary))
+            .map_err(|e| format!("Failed to write telemetry: {e}"))?;
+        self.written += (csv.len() + binary.len()) as u64;
+        Ok(())
+    }
+}
+
+fn csv_row(record: &TelemetryRecord) -> String {
+    match record {
+        TelemetryRecord::Sample(record) => {
+            let s = &record.sample;
+            let triple = |value: Option<(f32, f32, f32)>| match value {
+                Some((x, y, z)) => format!("{x},{y},{z}"),
+                None => ",,".to_string(),
+            };
+            format!(
+                "sample,{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\n",
+                record.time,
+                s.sim_time,
+                s.dt,
+                s.g_force.0,
+                s.g_force.1,
+                s.g_force.2,
+                triple(record.delta),
+                triple(record.filtered),
+                s.on_ground as u8,
+                s.groundspeed,
+                s.indicated_airspeed,
+                s.angle_of_attack,
+                s.gear_deploy,
+                s.flap_deploy,
+                s.paused as u8,
+                s.replay as u8,
+                s.in_menu as u8,
+            )
+        }
+        TelemetryRecord::Output { time, intensity } => {
+            format!(
+                "output,{time}{}{intensity}\n",
+                ",".repeat(CSV_SAMPLE_COLUMNS + 1)
+            )
+        }
+    }
+}
+
+fn encode(record: &TelemetryRecord) -> Vec<u8> {
+    let mut out = Vec::new();
+    let f32s = |out: &mut Vec<u8>, values: &[f32]| {
+        for value in values {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    };
+    match record {
+        TelemetryRecord::Sample(record) => {
+            let s = &record.sample;
+            let mut flags = 0;
+            for (set, flag) in [
+                (s.on_ground, FLAG_ON_GROUND),
+                (s.paused, FLAG_PAUSED),
+                (s.replay, FLAG_REPLAY),
+                (s.in_menu, FLAG_IN_MENU),
+                (record.delta.is_some(), FLAG_DELTA),
+                (record.filtered.is_some(), FLAG_FILTERED),
+            ] {
+                if set {
+                    flags |= flag;
+                }
+            }
+            let (dx, dy, dz) = record.delta.unwrap_or_default();
+            let (fx, fy, fz) = record.filtered.unwrap_or_default();
+            out.push(TAG_SAMPLE);
+            out.extend_from_slice(&record.time.to_le_bytes());
+            out.extend_from_slice(&s.sim_time.to_le_bytes());
+            out.push(flags);
+            f32s(
+                &mut out,
+                &[
+                    s.dt,
+                    s.g_force.0,
+                    s.g_force.1,
+                    s.g_force.2,
+                    s.groundspeed,
+                    s.indicated_airspeed,
+                    s.angle_of_attack,
+                    s.gear_deploy,
+                    s.flap_deploy,
+                    dx,
+                    dy,
+                    dz,
+                    fx,
+                    fy,
+                    fz,
+                ],
+            );
+        }
+        TelemetryRecord::Output { time, intensity } => {
+            out.push(TAG_OUTPUT);
+            out.extend_from_slice(&time.to_le_bytes());
+            out.push(*intensity);
+        }
+    }
+    out
+}
+
+/// Reads little-endian values off a byte slice.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl Cursor<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.bytes.len() < N {
+            return Err("Telemetry file ends in the middle of a record".to_string());
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    fn triple(&mut self) -> Result<(f32, f32, f32), String> {
+        Ok((self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+/// Read every record of a binary telemetry recording.
+pub fn read_binary<R: Read>(mut reader: R) -> Result<Vec<TelemetryRecord>, String> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read telemetry: {e}"))?;
+    let mut cursor = Cursor { bytes: &bytes };
+    if cursor.take::<8>().ok().as_ref() != Some(MAGIC) {
+        return Err("Not a telemetry recording".to_string());
+    }
+    let version = u16::from_le_bytes(cursor.take()?);
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported telemetry format version {version}"));
+    }
+
+    let mut records = Vec::new();
+    while !cursor.bytes.is_empty() {
+        let record = match cursor.u8()? {
+            TAG_SAMPLE => {
+                let time = cursor.f64()?;
+                let sim_time = cursor.f64()?;
+                let flags = cursor.u8()?;
+                let dt = cursor.f32()?;
+                let g_force = cursor.triple()?;
+                let sample = SimSample {
+                    sim_time,
+                    dt,
+                    g_force,
+                    on_ground: flags & FLAG_ON_GROUND != 0,
+                    groundspeed: cursor.f32()?,
+                    indicated_airspeed: cursor.f32()?,
+                    angle_of_attack: cursor.f32()?,
+                    gear_deploy: cursor.f32()?,
+                    flap_deploy: cursor.f32()?,
+                    paused: flags & FLAG_PAUSED != 0,
+                    replay: flags & FLAG_REPLAY != 0,
+                    in_menu: flags & FLAG_IN_MENU != 0,
+                };
+                let delta = cursor.triple()?;
+                let filtered = cursor.triple()?;
+                TelemetryRecord::Sample(SampleRecord {
+                    time,
+                    sample,
+                    delta: (flags & FLAG_DELTA != 0).then_some(delta),
+                    filtered: (flags & FLAG_FILTERED != 0).then_some(filtered),
+                })
+            }
+            TAG_OUTPUT => TelemetryRecord::Output {
+                time: cursor.f64()?,
+                intensity: cursor.u8()?,
+            },
+            tag => return Err(format!("Unknown telemetry record type {tag}")),
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Load a binary telemetry recording from a file.
+pub fn load_binary(path: &Path) -> Result<Vec<TelemetryRecord>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    read_binary(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<TelemetryRecord> {
+        vec![
+            TelemetryRecord::Sample(SampleRecord {
+                time: 0.0,
+                sample: SimSample {
+                    sim_time: 100.0,
+                    dt: 0.016,
+                    g_force: (0.0, 0.1, 1.0),
+                    on_ground: true,
+                    groundspeed: 40.0,
+                    in_menu: true,
+                    ..Default::default()
+                },
+                delta: None,
+                filtered: None,
+            }),
+            TelemetryRecord::Sample(SampleRecord {
+                time: 0.016,
+                sample: SimSample {
+                    sim_time: 100.016,
+                    dt: 0.016,
+                    g_force: (0.0, 0.1, 1.4),
+                    ..Default::default()
+                },
+                delta: Some((0.0, 0.0, 0.4)),
+                filtered: Some((0.0, 0.0, 0.35)),
+            }),
+            TelemetryRecord::Output {
+                time: 0.02,
+                intensity: 60,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let (mut csv, mut binary) = (Vec::new(), Vec::new());
+        let mut writer = TelemetryWriter::new(&mut csv, &mut binary, u64::MAX).unwrap();
+        for record in records() {
+            assert!(writer.write(&record).unwrap());
+        }
+        writer.finish().unwrap();
+        assert_eq!(read_binary(binary.as_slice()).unwrap(), records());
+
+        let csv = String::from_utf8(csv).unwrap();
+        let columns = CSV_HEADER.split(',').count();
+        for line in csv.lines() {
+            assert_eq!(line.split(',').count(), columns, "{line}");
+        }
+        assert!(csv.lines().last().unwrap().ends_with(",60"));
+    }
+
+    #[test]
+    fn test_size_cap() {
+        let (mut csv, mut binary) = (Vec::new(), Vec::new());
+        let mut writer = TelemetryWriter::new(&mut csv, &mut binary, 1000).unwrap();
+        let record = records()[1];
+        let mut written = 0;
+        while writer.write(&record).unwrap() {
+            written += 1;
+        }
+        assert!(written > 0);
+        assert!(writer.written() <= 1000);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(read_binary(&b"not telemetry"[..]).is_err());
+        let mut truncated = MAGIC.to_vec();
+        truncated.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        truncated.extend_from_slice(&[TAG_OUTPUT, 0, 0]);
+        assert!(read_binary(truncated.as_slice()).is_err());
+    }
+}
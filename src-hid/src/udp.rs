@@ -0,0 +1,409 @@
+//! X-Plane's UDP output: RREF dataref subscriptions and legacy "DATA" packets, turned into
+//! `SimSample`s for the vibration engine when X-Plane runs on another machine than the stick.
+
+use crate::engine::MIN_SAMPLE_DT;
+use crate::sample::SimSample;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Port X-Plane listens on for RREF requests.
+pub const XPLANE_PORT: u16 = 49000;
+
+/// Datarefs subscribed over RREF. The RREF index of each is its position here.
+pub const RREF_DATAREFS: [&str; 12] = [
+    "sim/time/total_running_time_sec",
+    "sim/flightmodel2/misc/gforce_side",
+    "sim/flightmodel2/misc/gforce_axil",
+    "sim/flightmodel2/misc/gforce_normal",
+    "sim/flightmodel/failures/onground_any",
+    "sim/flightmodel/position/groundspeed",
+    "sim/flightmodel/position/indicated_airspeed",
+    "sim/flightmodel2/misc/AoA_angle_degrees",
+    "sim/flightmodel2/gear/deploy_ratio[0]",
+    "sim/flightmodel2/controls/flap1_deploy_ratio",
+    "sim/time/paused",
+    "sim/operation/prefs/replay_mode",
+];
+
+/// Length of the dataref path field of an RREF request.
+const RREF_PATH_LEN: usize = 400;
+
+/// DATA group with the times; value 1 is the total time the sim has been running.
+const DATA_TIMES: i32 = 1;
+/// DATA group with the speeds; value 0 is the indicated airspeed in knots.
+const DATA_SPEEDS: i32 = 3;
+/// DATA group with Mach, VVI and g-load; values 4, 5 and 6 are the normal, axial and side g.
+const DATA_G_LOAD: i32 = 4;
+
+/// First bytes of a packet capture file.
+const CAPTURE_MAGIC: &[u8; 8] = b"XAURSAUP";
+
+/// Request X-Plane to send `dataref` `frequency` times per second, tagged with `index`.
+/// A frequency of 0 cancels the subscription.
+pub fn rref_request(index: i32, dataref: &str, frequency: i32) -> Vec<u8> {
+    let mut packet = b"RREF\0".to_vec();
+    packet.extend_from_slice(&frequency.to_le_bytes());
+    packet.extend_from_slice(&index.to_le_bytes());
+    let mut path = [0u8; RREF_PATH_LEN];
+    let len = dataref.len().min(RREF_PATH_LEN - 1);
+    path[..len].copy_from_slice(&dataref.as_bytes()[..len]);
+    packet.extend_from_slice(&path);
+    packet
+}
+
+/// A packet sent by X-Plane.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    /// `(index, value)` pairs of subscribed datarefs.
+    Rref(Vec<(i32, f32)>),
+    /// `(group, values)` of the data output groups selected in X-Plane's settings.
+    Data(Vec<(i32, [f32; 8])>),
+}
+
+fn le_i32(bytes: &[u8]) -> i32 {
+    i32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn le_f32(bytes: &[u8]) -> f32 {
+    f32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// Parse an RREF or DATA packet. Other packets X-Plane sends to the same port are errors.
+pub fn parse_packet(bytes: &[u8]) -> Result<Packet, String> {
+    // 4 bytes of header and one byte of padding/"internal use".
+    let (Some(header), Some(body)) = (bytes.get(..4), bytes.get(5..)) else {
+        return Err("Packet too short".to_string());
+    };
+    match header {
+        b"RREF" => {
+            if body.len() % 8 != 0 {
+                return Err(format!("Bad RREF packet length {}", bytes.len()));
+            }
+            Ok(Packet::Rref(
+                body.chunks(8)
+                    .map(|chunk| (le_i32(chunk), le_f32(&chunk[4..])))
+                    .collect(),
+            ))
+        }
+        b"DATA" => {
+            if body.len() % 36 != 0 {
+                return Err(format!("Bad DATA packet length {}", bytes.len()));
+            }
+            Ok(Packet::Data(
+                body.chunks(36)
+                    .map(|chunk| {
+                        let mut values = [0.0; 8];
+                        for (i, value) in values.iter_mut().enumerate() {
+                            *value = le_f32(&chunk[4 + i * 4..]);
+                        }
+                        (le_i32(chunk), values)
+                    })
+                    .collect(),
+            ))
+        }
+        _ => Err(format!(
+            "Unknown packet type {}",
+            String::from_utf8_lossy(header)
+        )),
+    }
+}
+
+/// Keeps the latest value of everything X-Plane sent, and turns each packet into a `SimSample`.
+#[derive(Default)]
+pub struct UdpSampler {
+    sample: SimSample,
+    /// X-Plane sent the sim time; otherwise the receive time stands in for it.
+    has_sim_time: bool,
+    /// Sim time of the previous sample, once X-Plane sends it.
+    last_sim_time: Option<f64>,
+    last_received: Option<f64>,
+}
+
+impl UdpSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in a packet received at `now` (seconds, any fixed origin) and return the sample as of
+    /// this packet. `None` if the packet carried nothing the engine uses.
+    pub fn receive(&mut self, packet: &Packet, now: f64) -> Option<SimSample> {
+        let mut updated = false;
+        match packet {
+            Packet::Rref(values) => {
+                for &(index, value) in values {
+                    updated |= self.set_rref(index, value);
+                }
+            }
+            Packet::Data(groups) => {
+                for (group, values) in groups {
+                    match *group {
+                        DATA_TIMES => {
+                            self.sample.sim_time = values[1] as f64;
+                            self.has_sim_time = true;
+                        }
+                        DATA_SPEEDS => self.sample.indicated_airspeed = values[0],
+                        DATA_G_LOAD => {
+                            self.sample.g_force = (values[6], values[5], values[4]);
+                        }
+                        _ => continue,
+                    }
+                    updated = true;
+                }
+            }
+        }
+        if !updated {
+            return None;
+        }
+
+        let last_received = self.last_received.replace(now);
+        if self.has_sim_time {
+            // Sim time keeps the deltas right however the network spaces the packets out.
+            let last = self.last_sim_time.replace(self.sample.sim_time);
+            self.sample.dt = last.map_or(0.0, |last| (self.sample.sim_time - last) as f32);
+        } else {
+            self.sample.sim_time = now;
+            self.sample.dt =
+                last_received.map_or(0.0, |last| ((now - last) as f32).max(MIN_SAMPLE_DT));
+        }
+        Some(self.sample)
+    }
+
+    fn set_rref(&mut self, index: i32, value: f32) -> bool {
+        let s = &mut self.sample;
+        match index {
+            0 => {
+                s.sim_time = value as f64;
+                self.has_sim_time = true;
+            }
+            1 => s.g_force.0 = value,
+            2 => s.g_force.1 = value,
+            3 => s.g_force.2 = value,
+            4 => s.on_ground = value != 0.0,
+            5 => s.groundspeed = value,
+            6 => s.indicated_airspeed = value,
+            7 => s.angle_of_attack = value,
+            8 => s.gear_deploy = value,
+            9 => s.flap_deploy = value,
+            10 => s.paused = value != 0.0,
+            11 => s.replay = value != 0.0,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Ask X-Plane at `xplane` to send every dataref of `RREF_DATAREFS` to `socket`,
+/// `frequency` times per second. A frequency of 0 cancels the subscriptions.
+pub fn subscribe(socket: &UdpSocket, xplane: SocketAddr, frequency: i32) -> Result<(), String> {
+    for (index, dataref) in RREF_DATAREFS.iter().enumerate() {
+        socket
+            .send_to(&rref_request(index as i32, dataref, frequency), xplane)
+            .map_err(|e| format!("Failed to subscribe to {dataref}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Saves received packets with their receive time, to be replayed by `serve_capture`.
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let mut file = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+        file.write_all(CAPTURE_MAGIC)
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        Ok(Self {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, packet: &[u8]) -> Result<(), String> {
+        let time = self.started.elapsed().as_secs_f64();
+        let len = u16::try_from(packet.len()).map_err(|_| "Packet too large".to_string())?;
+        self.file
+            .write_all(&time.to_le_bytes())
+            .and_then(|_| self.file.write_all(&len.to_le_bytes()))
+            .and_then(|_| self.file.write_all(packet))
+            .and_then(|_| self.file.flush())
+            .map_err(|e| format!("Failed to write capture: {e}"))
+    }
+}
+
+/// Read a packet capture: `(seconds since the capture started, packet)` in order.
+pub fn read_capture<R: Read>(mut reader: R) -> Result<Vec<(f64, Vec<u8>)>, String> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read capture: {e}"))?;
+    let Some(mut rest) = bytes.strip_prefix(CAPTURE_MAGIC.as_slice()) else {
+        return Err("Not a packet capture".to_string());
+    };
+    let mut packets = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 10 {
+            return Err("Capture ends in the middle of a packet".to_string());
+        }
+        let time = f64::from_le_bytes(rest[..8].try_into().unwrap());
+        let len = u16::from_le_bytes(rest[8..10].try_into().unwrap()) as usize;
+        let packet = rest
+            .get(10..10 + len)
+            .ok_or("Capture ends in the middle of a packet")?;
+        packets.push((time, packet.to_vec()));
+        rest = &rest[10 + len..];
+    }
+    Ok(packets)
+}
+
+/// Stand in for X-Plane: send the captured packets to `target` with their original timing.
+pub fn serve_capture(
+    packets: &[(f64, Vec<u8>)],
+    socket: &UdpSocket,
+    target: SocketAddr,
+) -> Result<(), String> {
+    let started = Instant::now();
+    for (time, packet) in packets {
+        let due = started + Duration::from_secs_f64(time.max(0.0));
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        socket
+            .send_to(packet, target)
+            .map_err(|e| format!("Failed to send to {target}: {e}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rref_packet(values: &[(i32, f32)]) -> Vec<u8> {
+        let mut packet = b"RREF,".to_vec();
+        for (index, value) in values {
+            packet.extend_from_slice(&index.to_le_bytes());
+            packet.extend_from_slice(&value.to_le_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn test_rref_request_layout() {
+        let request = rref_request(3, "sim/time/paused", 30);
+        assert_eq!(request.len(), 413);
+        assert_eq!(&request[..5], b"RREF\0");
+        assert_eq!(le_i32(&request[5..]), 30);
+        assert_eq!(le_i32(&request[9..]), 3);
+        assert_eq!(&request[13..28], b"sim/time/paused");
+        assert_eq!(request[28], 0);
+    }
+
+    #[test]
+    fn test_rref_packet_to_sample() {
+        let packet = parse_packet(&rref_packet(&[(0, 10.0), (3, 1.5), (10, 1.0)])).unwrap();
+        let mut sampler = UdpSampler::new();
+        let sample = sampler.receive(&packet, 0.0).unwrap();
+        assert_eq!(sample.sim_time, 10.0);
+        assert_eq!(sample.g_force, (0.0, 0.0, 1.5));
+        assert!(sample.paused);
+
+        let packet = parse_packet(&rref_packet(&[(0, 10.5), (3, 1.0)])).unwrap();
+        let sample = sampler.receive(&packet, 0.5).unwrap();
+        assert_eq!(sample.dt, 0.5);
+        assert!(sample.paused, "values not in the packet are kept");
+
+        // Packets delayed and delivered together still step by the sim time between them.
+        let packet = parse_packet(&rref_packet(&[(0, 10.75), (3, 1.0)])).unwrap();
+        assert_eq!(sampler.receive(&packet, 0.5).unwrap().dt, 0.25);
+    }
+
+    #[test]
+    fn test_dt_without_sim_time() {
+        let mut sampler = UdpSampler::new();
+        let g_load = |g: f32| {
+            let mut packet = b"DATA*".to_vec();
+            packet.extend_from_slice(&DATA_G_LOAD.to_le_bytes());
+            for value in [0.0f32, 0.0, 0.0, 0.0, g, 0.0, 0.0, 0.0] {
+                packet.extend_from_slice(&value.to_le_bytes());
+            }
+            parse_packet(&packet).unwrap()
+        };
+        sampler.receive(&g_load(1.0), 1.0).unwrap();
+        assert_eq!(sampler.receive(&g_load(1.1), 1.25).unwrap().dt, 0.25);
+        // A burst of packets doesn't make a near-zero dt.
+        assert_eq!(
+            sampler.receive(&g_load(1.2), 1.25).unwrap().dt,
+            MIN_SAMPLE_DT
+        );
+    }
+
+    #[test]
+    fn test_data_packet_to_sample() {
+        let mut packet = b"DATA*".to_vec();
+        for (group, values) in [
+            (4i32, [0.5f32, 0.0, 0.0, 0.0, 1.2, 0.1, -0.2, 0.0]),
+            (20, [0.0; 8]),
+        ] {
+            packet.extend_from_slice(&group.to_le_bytes());
+            for value in values {
+                packet.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        let packet = parse_packet(&packet).unwrap();
+        let mut sampler = UdpSampler::new();
+        let sample = sampler.receive(&packet, 2.0).unwrap();
+        assert_eq!(sample.g_force, (-0.2, 0.1, 1.2));
+        assert_eq!(sample.sim_time, 2.0);
+    }
+
+    #[test]
+    fn test_rejects_other_packets() {
+        assert!(parse_packet(b"BECN\0xxxx").is_err());
+        assert!(parse_packet(b"RREF,\x01\x00\x00").is_err());
+        assert!(parse_packet(b"RR").is_err());
+    }
+
+    #[test]
+    fn test_stand_in_replays_capture() {
+        let packets: Vec<(f64, Vec<u8>)> = (0..3)
+            .map(|frame| {
+                let time = frame as f64 * 0.01;
+                (
+                    time,
+                    rref_packet(&[(0, time as f32), (3, 1.0 + time as f32)]),
+                )
+            })
+            .collect();
+        let mut capture = CAPTURE_MAGIC.to_vec();
+        for (time, packet) in &packets {
+            capture.extend_from_slice(&time.to_le_bytes());
+            capture.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+            capture.extend_from_slice(packet);
+        }
+        let packets = read_capture(capture.as_slice()).unwrap();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let stand_in = UdpSocket::bind("127.0.0.1:0").unwrap();
+        serve_capture(&packets, &stand_in, receiver.local_addr().unwrap()).unwrap();
+
+        let mut sampler = UdpSampler::new();
+        let mut buf = [0u8; 2048];
+        let mut samples = Vec::new();
+        for _ in 0..3 {
+            let len = receiver.recv(&mut buf).unwrap();
+            let packet = parse_packet(&buf[..len]).unwrap();
+            samples.extend(sampler.receive(&packet, samples.len() as f64 * 0.01));
+        }
+        assert_eq!(samples.len(), 3);
+        assert!((samples[2].g_force.2 - 1.02).abs() < 1e-6);
+    }
+}
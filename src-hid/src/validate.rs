@@ -0,0 +1,31 @@
+//! Checks shared by the validation of profiles, patterns and engine parameters.
+
+/// Fail unless `value` is positive and finite. Written so NaN fails too: a NaN or infinite
+/// duration, rate or divisor would stall or never end what it drives.
+pub(crate) fn check_positive(name: &str, value: f32) -> Result<(), String> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{name} must be positive and finite, got {value}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_positive() {
+        for (value, valid) in [
+            (0.001, true),
+            (1e38, true),
+            (0.0, false),
+            (-1.0, false),
+            (f32::NAN, false),
+            (f32::INFINITY, false),
+            (f32::NEG_INFINITY, false),
+        ] {
+            assert_eq!(check_positive("x", value).is_ok(), valid, "{value}");
+        }
+    }
+}
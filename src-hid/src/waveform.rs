@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{PI, TAU};
+
+/// Number of random steps a noise burst takes per cycle.
+const NOISE_STEPS: f32 = 16.0;
+
+/// Shape of a single vibration wave. Every shape maps progress through the wave (0..1) to a
+/// level (0..1), which is then scaled by the wave's peak intensity.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Waveform {
+    /// Full sine of `frequency` cycles over the wave, with the negative arcs clipped.
+    #[default]
+    ClippedSine,
+    /// A single positive arc: starts at 0, up to 1, back to 0.
+    HalfSine,
+    /// On/off pulses, `frequency` per wave. `duty` is the fraction (0..1) of each pulse spent on.
+    SquarePulse { duty: f32 },
+    /// Linear ramp from 0 to 1, `frequency` times per wave.
+    Sawtooth,
+    /// Attack/decay/release are fractions (0..1) of the wave; `sustain` is a level (0..1).
+    Adsr {
+        attack: f32,
+        decay: f32,
+        sustain: f32,
+        release: f32,
+    },
+    /// Random levels, changing `NOISE_STEPS * frequency` times per wave.
+    Noise,
+    /// User-defined curve: `(progress, level)` points, linearly interpolated.
+    Keyframes { points: Vec<(f32, f32)> },
+}
+
+impl Waveform {
+    /// Check the shape parameters are in range. Returns a description of the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Waveform::SquarePulse { duty } if !(0.0..=1.0).contains(duty) => {
+                Err(format!("square pulse duty must be within 0..1, got {duty}"))
+            }
+            Waveform::Adsr {
+                attack,
+                decay,
+                sustain,
+                release,
+            } => {
+                for (name, value) in [
+                    ("attack", attack),
+                    ("decay", decay),
+                    ("sustain", sustain),
+                    ("release", release),
+                ] {
+                    if !(0.0..=1.0).contains(value) {
+                        return Err(format!("ADSR {name} must be within 0..1, got {value}"));
+                    }
+                }
+                if attack + decay + release > 1.0 {
+                    return Err("ADSR attack + decay + release must not exceed 1".to_string());
+                }
+                Ok(())
+            }
+            Waveform::Keyframes { points } => {
+                if points.is_empty() {
+                    return Err("keyframe curve needs at least one point".to_string());
+                }
+                let mut last_progress = 0.0;
+                for &(progress, level) in points {
+                    if !(0.0..=1.0).contains(&progress) || !(0.0..=1.0).contains(&level) {
+                        return Err(format!(
+                            "keyframe ({progress}, {level}) must be within 0..1 on both axes"
+                        ));
+                    }
+                    if progress < last_progress {
+                        return Err("keyframes must be sorted by progress".to_string());
+                    }
+                    last_progress = progress;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Return the level (0..1) at `progress` (0..1) through a wave.
+    /// `frequency` sets how many cycles periodic shapes repeat, `sharpness` raises the level to
+    /// that power for a steeper rise/fall, and `seed` picks the noise sequence.
+    pub fn sample(&self, progress: f32, frequency: f32, sharpness: f32, seed: u32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        let level = match self {
+            Waveform::ClippedSine => (progress * TAU * frequency).sin(),
+            Waveform::HalfSine => (progress * PI).sin(),
+            Waveform::SquarePulse { duty } => {
+                if (progress * frequency).fract() < *duty {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Waveform::Sawtooth => (progress * frequency).fract(),
+            Waveform::Adsr {
+                attack,
+                decay,
+                sustain,
+                release,
+            } => adsr(progress, *attack, *decay, *sustain, *release),
+            Waveform::Noise => noise(seed, (progress * NOISE_STEPS * frequency) as u32),
+            Waveform::Keyframes { points } => keyframes(points, progress),
+        };
+
+        // Negative arcs are clipped; raising to sharpness only applies to the positive part.
+        if level <= 0.0 {
+            0.0
+        } else {
+            level.min(1.0).powf(sharpness)
+        }
+    }
+}
+
+fn adsr(progress: f32, attack: f32, decay: f32, sustain: f32, release: f32) -> f32 {
+    let decay_end = attack + decay;
+    let release_start = 1.0 - release;
+    if progress < attack {
+        progress / attack
+    } else if progress < decay_end {
+        1.0 - (1.0 - sustain) * (progress - attack) / decay
+    } else if progress < release_start {
+        sustain
+    } else if release > 0.0 {
+        sustain * (1.0 - progress) / release
+    } else {
+        sustain
+    }
+}
+
+/// Cheap deterministic hash of (seed, step) into 0..1, so a wave's noise is stable between updates.
+fn noise(seed: u32, step: u32) -> f32 {
+    let mut x = seed.wrapping_mul(0x9E37_79B9) ^ step.wrapping_mul(0x85EB_CA6B);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    x as f32 / u32::MAX as f32
+}
+
+fn keyframes(points: &[(f32, f32)], progress: f32) -> f32 {
+    let Some(&(first_progress, first_level)) = points.first() else {
+        return 0.0;
+    };
+    if progress <= first_progress {
+        return first_level;
+    }
+    for pair in points.windows(2) {
+        let (p0, l0) = pair[0];
+        let (p1, l1) = pair[1];
+        if progress <= p1 {
+            if p1 <= p0 {
+                return l1;
+            }
+            return l0 + (l1 - l0) * (progress - p0) / (p1 - p0);
+        }
+    }
+    points.last().map(|&(_, level)| level).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_stay_in_range() {
+        let shapes = [
+            Waveform::ClippedSine,
+            Waveform::HalfSine,
+            Waveform::SquarePulse { duty: 0.3 },
+            Waveform::Sawtooth,
+            Waveform::Adsr {
+                attack: 0.1,
+                decay: 0.2,
+                sustain: 0.5,
+                release: 0.3,
+            },
+            Waveform::Noise,
+            Waveform::Keyframes {
+                points: vec![(0.0, 0.0), (0.5, 1.0), (1.0, 0.0)],
+            },
+        ];
+        for shape in shapes.iter() {
+            for i in 0..=100 {
+                let level = shape.sample(i as f32 / 100.0, 3.0, 2.0, 7);
+                assert!((0.0..=1.0).contains(&level), "{shape:?} gave {level}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_half_sine_peaks_in_the_middle() {
+        let shape = Waveform::HalfSine;
+        assert!(shape.sample(0.0, 1.0, 1.0, 0) < 0.01);
+        assert!((shape.sample(0.5, 1.0, 1.0, 0) - 1.0).abs() < 0.01);
+        assert!(shape.sample(1.0, 1.0, 1.0, 0) < 0.01);
+    }
+
+    #[test]
+    fn test_adsr_envelope() {
+        let shape = Waveform::Adsr {
+            attack: 0.2,
+            decay: 0.2,
+            sustain: 0.5,
+            release: 0.2,
+        };
+        assert!((shape.sample(0.1, 1.0, 1.0, 0) - 0.5).abs() < 0.01);
+        assert!((shape.sample(0.2, 1.0, 1.0, 0) - 1.0).abs() < 0.01);
+        assert!((shape.sample(0.6, 1.0, 1.0, 0) - 0.5).abs() < 0.01);
+        assert!((shape.sample(0.9, 1.0, 1.0, 0) - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_keyframes_interpolate() {
+        let shape = Waveform::Keyframes {
+            points: vec![(0.0, 0.0), (0.5, 1.0), (1.0, 0.5)],
+        };
+        assert!((shape.sample(0.25, 1.0, 1.0, 0) - 0.5).abs() < 0.01);
+        assert!((shape.sample(0.75, 1.0, 1.0, 0) - 0.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_noise_is_deterministic_per_seed() {
+        let shape = Waveform::Noise;
+        assert_eq!(
+            shape.sample(0.3, 1.0, 1.0, 42),
+            shape.sample(0.3, 1.0, 1.0, 42)
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unsorted_keyframes() {
+        let shape = Waveform::Keyframes {
+            points: vec![(0.5, 1.0), (0.2, 0.0)],
+        };
+        assert!(shape.validate().is_err());
+        assert!(Waveform::SquarePulse { duty: 1.5 }.validate().is_err());
+        // Range checks reject NaN and infinity as well.
+        assert!(Waveform::SquarePulse { duty: f32::NAN }.validate().is_err());
+        let shape = Waveform::Keyframes {
+            points: vec![(0.0, f32::INFINITY)],
+        };
+        assert!(shape.validate().is_err());
+    }
+}
@@ -0,0 +1,559 @@
+use crate::hid::HIDWrapper;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Writes slower than this count as timed out: the device is reopened after one returns late, and
+/// a thread still stuck in one is replaced (see `HidWriter`).
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The writes `HidWriter` makes, so tests can stand in for the stick.
+pub trait HidOutput: Send + 'static {
+    fn write_vibration(&mut self, intensity: u8) -> Result<(), String>;
+    fn write_backlight(&mut self, level: u8) -> Result<(), String>;
+    /// Open the device again, e.g. after it was unplugged or a write got stuck.
+    fn reopen(&mut self) -> Result<(), String>;
+    /// Serial number of the open device, if any. Must not touch the device.
+    fn serial_number(&self) -> Option<&str>;
+}
+
+impl HidOutput for HIDWrapper {
+    fn write_vibration(&mut self, intensity: u8) -> Result<(), String> {
+        HIDWrapper::write_vibration(self, intensity)
+    }
+
+    fn write_backlight(&mut self, level: u8) -> Result<(), String> {
+        HIDWrapper::write_backlight(self, level)
+    }
+
+    fn reopen(&mut self) -> Result<(), String> {
+        self.open()
+    }
+
+    fn serial_number(&self) -> Option<&str> {
+        HIDWrapper::serial_number(self)
+    }
+}
+
+/// Counters of the writes made so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WriteStats {
+    pub writes: u64,
+    pub failures: u64,
+    /// Writes that took longer than the timeout: returned late, or abandoned while stuck.
+    pub timeouts: u64,
+    /// Writer threads abandoned in a stuck write and replaced by one with a fresh handle.
+    pub replaced: u64,
+    /// Values replaced by a newer one before they were written.
+    pub coalesced: u64,
+    pub last_latency: Duration,
+    pub max_latency: Duration,
+    pub total_latency: Duration,
+}
+
+impl WriteStats {
+    pub fn mean_latency(&self) -> Duration {
+        if self.writes == 0 {
+            Duration::ZERO
+        } else {
+            self.total_latency / self.writes as u32
+        }
+    }
+
+    fn add(&mut self, latency: Duration, ok: bool, timeout: Duration) {
+        self.writes += 1;
+        self.failures += u64::from(!ok);
+        self.timeouts += u64::from(latency > timeout);
+        self.last_latency = latency;
+        self.max_latency = self.max_latency.max(latency);
+        self.total_latency += latency;
+    }
+}
+
+/// What the writer thread does next. Reopening comes first so the values after it reach the new
+/// handle; the motor comes before the backlight since its timing is felt.
+#[derive(Clone, Copy)]
+enum Job {
+    Reopen,
+    Motor(u8),
+    Backlight(u8),
+}
+
+/// Latest value of every kind of write, waiting for the writer thread.
+#[derive(Default)]
+struct State {
+    motor: Option<u8>,
+    backlight: Option<u8>,
+    reopen: bool,
+    stop: bool,
+    /// The write in progress, and when it started.
+    in_flight: Option<(Instant, Job)>,
+    /// Number of the writer thread in charge. A thread that was replaced exits once its write
+    /// returns, if it ever does.
+    worker: u64,
+    /// A caller is opening a handle to replace the stuck thread, outside the lock.
+    replacing: bool,
+    stats: WriteStats,
+    /// Serial number of the device as of the last write.
+    serial: Option<String>,
+}
+
+impl State {
+    fn next_job(&mut self) -> Option<Job> {
+        if std::mem::take(&mut self.reopen) {
+            Some(Job::Reopen)
+        } else if let Some(intensity) = self.motor.take() {
+            Some(Job::Motor(intensity))
+        } else {
+            self.backlight.take().map(Job::Backlight)
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.motor.is_none() && self.backlight.is_none() && !self.reopen && self.in_flight.is_none()
+    }
+
+    fn stalled(&self, timeout: Duration) -> bool {
+        self.in_flight
+            .is_some_and(|(started, _)| started.elapsed() > timeout)
+    }
+}
+
+type Open = dyn Fn() -> Result<Box<dyn HidOutput>, String> + Send + Sync;
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a value is queued, and when a write finished.
+    changed: Condvar,
+    timeout: Duration,
+    /// Opens a fresh handle for a thread replacing a stuck one.
+    open: Box<Open>,
+    on_error: Box<dyn Fn(&str) + Send + Sync>,
+}
+
+impl Shared {
+    /// Body of writer thread number `worker`, which owns `device`.
+    fn run(&self, mut device: Box<dyn HidOutput>, worker: u64) {
+        loop {
+            let job = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if state.worker != worker {
+                        return;
+                    }
+                    if let Some(job) = state.next_job() {
+                        state.in_flight = Some((Instant::now(), job));
+                        break job;
+                    }
+                    if state.stop {
+                        return;
+                    }
+                    state = self.changed.wait(state).unwrap();
+                }
+            };
+
+            let started = Instant::now();
+            let result = match job {
+                Job::Reopen => device.reopen(),
+                Job::Motor(intensity) => device.write_vibration(intensity),
+                Job::Backlight(level) => device.write_backlight(level),
+            };
+            let latency = started.elapsed();
+
+            let mut state = self.state.lock().unwrap();
+            if state.worker != worker {
+                // Given up on while stuck in this write: a new thread owns the device now.
+                return;
+            }
+            if let Err(e) = &result {
+                (self.on_error)(e);
+            }
+            let reopened = matches!(job, Job::Reopen);
+            if !reopened && latency > self.timeout {
+                (self.on_error)(&format!(
+                    "HID write took {} ms, reopening the device",
+                    latency.as_millis()
+                ));
+            }
+            state.in_flight = None;
+            if state.serial.as_deref() != device.serial_number() {
+                state.serial = device.serial_number().map(str::to_string);
+            }
+            if !reopened {
+                state.stats.add(latency, result.is_ok(), self.timeout);
+                state.reopen |= latency > self.timeout;
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// If the write in progress has been stuck for longer than the timeout, give up on its
+    /// thread and start a new one with a fresh handle. Returns the new thread.
+    ///
+    /// Opening can block, and the caller may be the simulator's main thread, so the lock is
+    /// released meanwhile; the stuck write is only replaced if it's still stuck afterwards.
+    fn replace_stalled<'a>(
+        self: &'a Arc<Self>,
+        mut state: MutexGuard<'a, State>,
+    ) -> (MutexGuard<'a, State>, Option<JoinHandle<()>>) {
+        if state.replacing || !state.stalled(self.timeout) {
+            return (state, None);
+        }
+        let Some((started, job)) = state.in_flight else {
+            return (state, None);
+        };
+        let worker = state.worker;
+        state.replacing = true;
+        drop(state);
+
+        let opened = (self.open)();
+        if let Err(e) = &opened {
+            (self.on_error)(&format!("Could not replace a stuck HID write: {e}"));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.replacing = false;
+        let still_stuck = state.worker == worker
+            && state
+                .in_flight
+                .is_some_and(|(in_flight, _)| in_flight == started);
+        if !still_stuck {
+            // The write returned while the new handle was being opened; it isn't needed.
+            return (state, None);
+        }
+        let device = match opened {
+            Ok(device) => device,
+            Err(_) => {
+                // Try again after another timeout rather than on every call.
+                state.in_flight = Some((Instant::now(), job));
+                return (state, None);
+            }
+        };
+        (self.on_error)(&format!(
+            "HID write stuck for {} ms, carrying on with a fresh handle",
+            started.elapsed().as_millis()
+        ));
+        state.worker += 1;
+        state.in_flight = None;
+        state.stats.timeouts += 1;
+        state.stats.replaced += 1;
+        // The stuck value may never have arrived: write it again unless a newer one is queued.
+        match job {
+            Job::Reopen => {}
+            Job::Motor(intensity) => {
+                state.motor.get_or_insert(intensity);
+            }
+            Job::Backlight(level) => {
+                state.backlight.get_or_insert(level);
+            }
+        }
+        state.serial = device.serial_number().map(str::to_string);
+        let shared = Arc::clone(self);
+        let worker = state.worker;
+        (
+            state,
+            Some(thread::spawn(move || shared.run(device, worker))),
+        )
+    }
+}
+
+/// Owns the device on a thread of its own, so a slow or stuck USB write never stalls the caller.
+/// Only the latest motor and backlight values are kept: a value that is replaced before the
+/// thread gets to it is never written.
+///
+/// A write can't be interrupted, so the timeout is enforced from the outside: a write that
+/// returns late gets the device reopened, and one still stuck when the writer is next used is
+/// abandoned along with its thread and handle, and the writes carry on from a new thread with a
+/// freshly opened handle. The abandoned thread only ends once the stuck call returns; if the OS
+/// never returns it, the thread and its handle are leaked, and a device that can only be opened
+/// once stays unreachable until then.
+pub struct HidWriter {
+    shared: Arc<Shared>,
+    /// The thread in charge; replaced threads are detached.
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HidWriter {
+    /// Open the device with `open` and start the writer thread. `open` is called again to
+    /// replace a stuck thread. `on_error` is called for every failed or timed out write.
+    pub fn start<D: HidOutput>(
+        open: impl Fn() -> Result<D, String> + Send + Sync + 'static,
+        timeout: Duration,
+        on_error: impl Fn(&str) + Send + Sync + 'static,
+    ) -> Result<Self, String> {
+        let open = move || open().map(|device| Box::new(device) as Box<dyn HidOutput>);
+        let device = open()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                serial: device.serial_number().map(str::to_string),
+                ..Default::default()
+            }),
+            changed: Condvar::new(),
+            timeout,
+            open: Box::new(open),
+            on_error: Box::new(on_error),
+        });
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || thread_shared.run(device, 0));
+        Ok(Self {
+            shared,
+            thread: Mutex::new(Some(thread)),
+        })
+    }
+
+    /// Queue a motor intensity, replacing the one not written yet.
+    pub fn set_motor(&self, intensity: u8) {
+        self.queue(|state| state.motor.replace(intensity).is_some());
+    }
+
+    /// Queue a backlight level, replacing the one not written yet.
+    pub fn set_backlight(&self, level: u8) {
+        self.queue(|state| state.backlight.replace(level).is_some());
+    }
+
+    /// Reopen the device before the next write.
+    pub fn reopen(&self) {
+        self.queue(|state| std::mem::replace(&mut state.reopen, true));
+    }
+
+    /// `queue` returns whether it replaced a pending value.
+    fn queue(&self, queue: impl FnOnce(&mut State) -> bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        if queue(&mut state) {
+            state.stats.coalesced += 1;
+        }
+        let _state = self.replace_stalled(state);
+        self.shared.changed.notify_all();
+    }
+
+    fn replace_stalled<'a>(&'a self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        let (state, thread) = self.shared.replace_stalled(state);
+        if let Some(thread) = thread {
+            *self.thread.lock().unwrap() = Some(thread);
+        }
+        state
+    }
+
+    /// Wait until everything queued has been written, for at most `timeout`. Returns whether it
+    /// was. A write stuck meanwhile is handed to a new thread.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            state = self.replace_stalled(state);
+            if state.idle() {
+                return true;
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            // Wake up in time to notice a stuck write.
+            let wait = remaining.min(self.shared.timeout);
+            state = self.shared.changed.wait_timeout(state, wait).unwrap().0;
+        }
+    }
+
+    pub fn stats(&self) -> WriteStats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    /// Serial number of the device, or `None` while it isn't open. Doesn't touch the device.
+    pub fn serial_number(&self) -> Option<String> {
+        self.shared.state.lock().unwrap().serial.clone()
+    }
+
+    /// Whether the write in progress has been running for longer than the timeout. It's handed
+    /// to a new thread the next time a value is queued or the writer is flushed.
+    pub fn stalled(&self) -> bool {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .stalled(self.shared.timeout)
+    }
+}
+
+impl Drop for HidWriter {
+    /// Write what's still queued, then end the thread. A thread stuck in a write is left behind
+    /// rather than hanging the caller.
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stop = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if !self.stalled() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Write {
+        Motor(u8),
+        Backlight(u8),
+        Reopen,
+    }
+
+    /// Records the writes and takes `delay` for each.
+    #[derive(Clone)]
+    struct FakeDevice {
+        writes: Arc<Mutex<Vec<Write>>>,
+        delay: Duration,
+        fail: bool,
+    }
+
+    impl FakeDevice {
+        /// Open `self` again for every writer thread.
+        fn opener(&self) -> impl Fn() -> Result<Self, String> + Send + Sync + 'static {
+            let device = self.clone();
+            move || Ok(device.clone())
+        }
+
+        fn new(delay: Duration) -> Self {
+            Self {
+                writes: Arc::new(Mutex::new(Vec::new())),
+                delay,
+                fail: false,
+            }
+        }
+
+        fn record(&mut self, write: Write) -> Result<(), String> {
+            thread::sleep(self.delay);
+            self.writes.lock().unwrap().push(write);
+            if self.fail {
+                Err("write failed".to_string())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn writes(&self) -> Vec<Write> {
+            self.writes.lock().unwrap().clone()
+        }
+    }
+
+    impl HidOutput for FakeDevice {
+        fn write_vibration(&mut self, intensity: u8) -> Result<(), String> {
+            self.record(Write::Motor(intensity))
+        }
+
+        fn write_backlight(&mut self, level: u8) -> Result<(), String> {
+            self.record(Write::Backlight(level))
+        }
+
+        fn reopen(&mut self) -> Result<(), String> {
+            self.writes.lock().unwrap().push(Write::Reopen);
+            Ok(())
+        }
+
+        fn serial_number(&self) -> Option<&str> {
+            (!self.fail).then_some("XA-0001")
+        }
+    }
+
+    #[test]
+    fn test_latest_value_wins() {
+        let device = FakeDevice::new(Duration::from_millis(20));
+        let writer = HidWriter::start(device.opener(), Duration::from_secs(1), |_| {}).unwrap();
+        for level in 1..=10 {
+            writer.set_backlight(level);
+            writer.set_motor(level * 10);
+        }
+        assert!(writer.flush(Duration::from_secs(2)));
+
+        let writes = device.writes();
+        assert!(writes.len() < 20);
+        let last = |motor: bool| {
+            writes
+                .iter()
+                .rev()
+                .find(|write| matches!(write, Write::Motor(_)) == motor)
+                .cloned()
+        };
+        assert_eq!(last(true), Some(Write::Motor(100)));
+        assert_eq!(last(false), Some(Write::Backlight(10)));
+        let stats = writer.stats();
+        assert_eq!(stats.writes as usize, writes.len());
+        assert_eq!(stats.coalesced as usize, 20 - writes.len());
+        assert!(stats.max_latency >= Duration::from_millis(20));
+        assert!(stats.mean_latency() <= stats.max_latency);
+    }
+
+    #[test]
+    fn test_slow_write_times_out_and_reopens() {
+        let device = FakeDevice::new(Duration::from_millis(30));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&errors);
+        let writer = HidWriter::start(device.opener(), Duration::from_millis(10), move |e| {
+            reported.lock().unwrap().push(e.to_string())
+        })
+        .unwrap();
+        writer.set_motor(200);
+        thread::sleep(Duration::from_millis(20));
+        assert!(writer.stalled());
+        // Left alone, the write returns late and the device is reopened on the same thread.
+        thread::sleep(Duration::from_millis(30));
+        assert!(writer.flush(Duration::from_secs(1)));
+
+        assert_eq!(device.writes(), vec![Write::Motor(200), Write::Reopen]);
+        assert_eq!(writer.stats().timeouts, 1);
+        assert_eq!(writer.serial_number().as_deref(), Some("XA-0001"));
+        assert_eq!(errors.lock().unwrap().len(), 1);
+        assert_eq!(writer.stats().replaced, 0);
+        assert!(!writer.stalled());
+    }
+
+    #[test]
+    fn test_stuck_write_moves_to_a_fresh_handle() {
+        let stuck = FakeDevice::new(Duration::from_millis(500));
+        let fresh = FakeDevice {
+            delay: Duration::ZERO,
+            ..stuck.clone()
+        };
+        let log = stuck.clone();
+        let opened = AtomicUsize::new(0);
+        let open = move || match opened.fetch_add(1, Ordering::Relaxed) {
+            0 => Ok(stuck.clone()),
+            _ => Ok(fresh.clone()),
+        };
+        let writer = HidWriter::start(open, Duration::from_millis(10), |_| {}).unwrap();
+        writer.set_motor(200);
+        thread::sleep(Duration::from_millis(30));
+        assert!(writer.stalled());
+
+        // The stuck value is written again through the new handle, long before the old one
+        // returns.
+        let started = Instant::now();
+        assert!(writer.flush(Duration::from_secs(1)));
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert_eq!(log.writes(), vec![Write::Motor(200)]);
+        let stats = writer.stats();
+        assert_eq!((stats.replaced, stats.timeouts), (1, 1));
+        assert!(!writer.stalled());
+    }
+
+    #[test]
+    fn test_failures_are_counted_and_pending_writes_finish_on_drop() {
+        let mut device = FakeDevice::new(Duration::ZERO);
+        device.fail = true;
+        let writer = HidWriter::start(device.opener(), Duration::from_secs(1), |_| {}).unwrap();
+        writer.set_motor(0);
+        writer.set_backlight(255);
+        drop(writer);
+        assert_eq!(
+            device.writes(),
+            vec![Write::Motor(0), Write::Backlight(255)]
+        );
+
+        let writer = HidWriter::start(device.opener(), Duration::from_secs(1), |_| {}).unwrap();
+        writer.set_motor(1);
+        writer.flush(Duration::from_secs(1));
+        assert_eq!(writer.stats().failures, 1);
+        assert_eq!(writer.serial_number(), None);
+    }
+}
@@ -1,10 +1,43 @@
 use std::{thread, time};
 use xa_ursa_minor_hid::hid::HIDWrapper;
+use xa_ursa_minor_hid::ipc::{
+    default_endpoint, device_owner, DeviceOwner, IpcClient, DEVICE_IN_USE,
+};
+use xa_ursa_minor_hid::pattern::Pattern;
+use xa_ursa_minor_hid::profile::Profile;
+
+/// Played through the plugin instead of `test_ursa_minor`'s ramp while the plugin owns the device
+const TEST_PATTERN: &str = r#"{
+    "name": "Test",
+    "segments": [{ "intensity": 1.0, "duration": 2.0, "curve": { "type": "sawtooth" } }]
+}"#;
+
+/// What commands return for the result of a plugin request
+fn outcome(result: Result<(), String>) -> String {
+    match result {
+        Ok(()) => "Success".to_string(),
+        Err(e) => e,
+    }
+}
+
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
 fn get_sn() -> String {
+    // The plugin reports the serial number of the device it owns; leave the device alone then
+    match device_owner(&default_endpoint()) {
+        DeviceOwner::Plugin(mut plugin) => {
+            return plugin
+                .status()
+                .ok()
+                .and_then(|status| status.serial_number)
+                .unwrap_or_default();
+        }
+        DeviceOwner::Unreachable(_) => return "".to_string(),
+        DeviceOwner::Nobody => {}
+    }
+
     // Attempt to create our HID wrapper
-    let Some(hid_wrapper) = HIDWrapper::new() else {
+    let Some(mut hid_wrapper) = HIDWrapper::new() else {
         return "".to_string();
     };
 
@@ -21,8 +54,13 @@ fn restart_ursa_minor() -> String {
         0x02, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ];
 
+    // Never restart the stick under the plugin's feet
+    if !matches!(device_owner(&default_endpoint()), DeviceOwner::Nobody) {
+        return DEVICE_IN_USE.to_string();
+    }
+
     // Attempt to create our HID wrapper
-    let Some(hid_wrapper) = HIDWrapper::new() else {
+    let Some(mut hid_wrapper) = HIDWrapper::new() else {
         return "".to_string();
     };
 
@@ -35,8 +73,18 @@ fn restart_ursa_minor() -> String {
 
 #[tauri::command]
 fn test_ursa_minor() -> String {
+    // While X-Plane runs, the plugin owns the device and plays the test for us
+    match device_owner(&default_endpoint()) {
+        DeviceOwner::Plugin(mut plugin) => {
+            let pattern = Pattern::from_json(TEST_PATTERN).expect("the test pattern is valid");
+            return outcome(plugin.play_pattern(pattern));
+        }
+        DeviceOwner::Unreachable(e) => return e,
+        DeviceOwner::Nobody => {}
+    }
+
     // Attempt to create our HID wrapper
-    let Some(hid_wrapper) = HIDWrapper::new() else {
+    let Some(mut hid_wrapper) = HIDWrapper::new() else {
         return "".to_string();
     };
 
@@ -72,10 +120,61 @@ fn test_ursa_minor() -> String {
     "Success".to_string()
 }
 
+// Async so Tauri runs it off the main thread: a long pattern must not freeze the window
+#[tauri::command]
+async fn preview_pattern(pattern: String) -> String {
+    // Parse the pattern first so authoring errors are reported back to the UI. The plugin plays
+    // it while X-Plane runs, the app itself otherwise
+    let pattern = match Pattern::from_json(&pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => return e.to_string(),
+    };
+
+    match device_owner(&default_endpoint()) {
+        DeviceOwner::Plugin(mut plugin) => return outcome(plugin.play_pattern(pattern)),
+        DeviceOwner::Unreachable(e) => return e,
+        DeviceOwner::Nobody => {}
+    }
+
+    // Attempt to create our HID wrapper
+    let Some(mut hid_wrapper) = HIDWrapper::new() else {
+        return "".to_string();
+    };
+
+    // Play the pattern until it finishes. Validation caps its length, and the deadline makes sure
+    // the loop ends even if intensity_at disagrees with total_duration
+    let start = time::Instant::now();
+    let deadline = time::Duration::from_secs_f32(pattern.total_duration());
+    while start.elapsed() < deadline {
+        let Some(intensity) = pattern.intensity_at(start.elapsed().as_secs_f32()) else {
+            break;
+        };
+        if let Err(e) = hid_wrapper.write_vibration(intensity) {
+            eprintln!("Failed to send command: {}", e);
+            return "Failed".to_string();
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+
+    // Always leave the motor off
+    if let Err(e) = hid_wrapper.write_vibration(0) {
+        eprintln!("Failed to send final command: {}", e);
+        return "Failed".to_string();
+    }
+
+    "Success".to_string()
+}
+
 #[tauri::command]
 fn lights_off() -> String {
+    match device_owner(&default_endpoint()) {
+        DeviceOwner::Plugin(mut plugin) => return outcome(plugin.set_backlight(0)),
+        DeviceOwner::Unreachable(e) => return e,
+        DeviceOwner::Nobody => {}
+    }
+
     // Attempt to create our HID wrapper
-    let Some(hid_wrapper) = HIDWrapper::new() else {
+    let Some(mut hid_wrapper) = HIDWrapper::new() else {
         return "".to_string();
     };
 
@@ -88,8 +187,14 @@ fn lights_off() -> String {
 
 #[tauri::command]
 fn lights_on() -> String {
+    match device_owner(&default_endpoint()) {
+        DeviceOwner::Plugin(mut plugin) => return outcome(plugin.set_backlight(255)),
+        DeviceOwner::Unreachable(e) => return e,
+        DeviceOwner::Nobody => {}
+    }
+
     // Attempt to create our HID wrapper
-    let Some(hid_wrapper) = HIDWrapper::new() else {
+    let Some(mut hid_wrapper) = HIDWrapper::new() else {
         return "".to_string();
     };
 
@@ -100,6 +205,30 @@ fn lights_on() -> String {
     }
 }
 
+#[tauri::command]
+fn plugin_status() -> String {
+    // The status as JSON, including the live motor intensity, or the reason it's unavailable
+    // (e.g. X-Plane isn't running)
+    let status = IpcClient::connect(&default_endpoint()).and_then(|mut client| client.status());
+    match status {
+        Ok(status) => serde_json::to_string(&status).unwrap_or_default(),
+        Err(e) => e,
+    }
+}
+
+#[tauri::command]
+fn push_profile(profile: String) -> String {
+    // Parse the profile first so authoring errors are reported back to the UI
+    let profile = match Profile::from_json(&profile) {
+        Ok(profile) => profile,
+        Err(e) => return e,
+    };
+
+    outcome(
+        IpcClient::connect(&default_endpoint()).and_then(|mut client| client.set_profile(profile)),
+    )
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -108,8 +237,11 @@ pub fn run() {
             get_sn,
             restart_ursa_minor,
             test_ursa_minor,
+            preview_pattern,
             lights_off,
             lights_on,
+            plugin_status,
+            push_profile,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,189 @@
+use crate::plugin_debugln;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use xa_ursa_minor_hid::writer::HidWriter;
+
+/// Time between two backlight levels while fading.
+const FADE_STEP_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long a blocking fade waits to reach its target: a full-range fade takes 1.3 s.
+const FADE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a blocking fade waits for its last level to reach the device.
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What drives the backlight while the plugin is enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BacklightMode {
+    /// Full brightness.
+    On,
+    /// Dark.
+    Off,
+    /// Follow the aircraft's instrument brightness.
+    Auto,
+}
+
+/// Where the fade thread is headed.
+#[derive(Default)]
+struct Fade {
+    /// Level being faded to; `None` once reached, or after the fade was cancelled.
+    target: Option<u8>,
+    stop: bool,
+}
+
+struct Shared {
+    writer: Arc<HidWriter>,
+    /// Last level written to the device.
+    level: AtomicU8,
+    fade: Mutex<Fade>,
+    /// Signalled when the target changes or is reached.
+    changed: Condvar,
+}
+
+impl Shared {
+    fn write(&self, level: u8) {
+        // A fade queues levels faster than the device may take them; only the latest is written.
+        self.writer.set_backlight(level);
+        self.level.store(level, Ordering::Relaxed);
+    }
+
+    /// Body of the fade thread: step towards the current target, one level every
+    /// `FADE_STEP_INTERVAL`, then sleep until a new one is set. A new target takes over from
+    /// the level reached so far.
+    fn run_fades(&self) {
+        let mut fade = self.fade.lock().unwrap();
+        loop {
+            if fade.stop {
+                return;
+            }
+            let Some(target) = fade.target else {
+                fade = self.changed.wait(fade).unwrap();
+                continue;
+            };
+            let level = self.level.load(Ordering::Relaxed);
+            if level == target {
+                fade.target = None;
+                self.changed.notify_all();
+                continue;
+            }
+            // Written under the lock, like `Backlight::set`, so a level set meanwhile is never
+            // overwritten by this step; `write` only queues it, so the lock isn't held for long.
+            self.write(if level < target { level + 1 } else { level - 1 });
+            drop(fade);
+            thread::sleep(FADE_STEP_INTERVAL);
+            fade = self.fade.lock().unwrap();
+        }
+    }
+}
+
+/// Runs the fades of every clone of a `Backlight`; stopped when the last clone is dropped.
+struct FadeThread {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for FadeThread {
+    fn drop(&mut self) {
+        self.shared.fade.lock().unwrap().stop = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Backlight brightness of the stick, written through the shared `HidWriter`.
+/// Cheap to clone: every clone drives the same device and sees the same level.
+#[derive(Clone)]
+pub struct Backlight {
+    shared: Arc<Shared>,
+    _fades: Arc<FadeThread>,
+    mode: Arc<Mutex<BacklightMode>>,
+}
+
+impl Backlight {
+    pub fn new(writer: Arc<HidWriter>) -> Self {
+        let shared = Arc::new(Shared {
+            writer,
+            level: AtomicU8::new(0),
+            fade: Mutex::new(Fade::default()),
+            changed: Condvar::new(),
+        });
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || thread_shared.run_fades());
+        Self {
+            _fades: Arc::new(FadeThread {
+                shared: Arc::clone(&shared),
+                thread: Some(thread),
+            }),
+            shared,
+            mode: Arc::new(Mutex::new(BacklightMode::On)),
+        }
+    }
+
+    pub fn mode(&self) -> BacklightMode {
+        *self.mode.lock().unwrap()
+    }
+
+    /// Switch mode. On and Off fade to their level; Auto is applied by the flight loop.
+    pub fn set_mode(&self, mode: BacklightMode) {
+        *self.mode.lock().unwrap() = mode;
+        match mode {
+            BacklightMode::On => self.fade_to(255),
+            BacklightMode::Off => self.fade_to(0),
+            BacklightMode::Auto => {}
+        }
+    }
+
+    /// Level the mode asks for when the plugin is enabled, if it sets one.
+    pub fn mode_level(&self) -> Option<u8> {
+        match self.mode() {
+            BacklightMode::On => Some(255),
+            BacklightMode::Off => Some(0),
+            BacklightMode::Auto => None,
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.shared.level.load(Ordering::Relaxed)
+    }
+
+    /// Set the level immediately, cancelling any fade in progress.
+    pub fn set(&self, level: u8) {
+        let mut fade = self.shared.fade.lock().unwrap();
+        fade.target = None;
+        // Written under the lock so the fade thread can't overwrite it with a stale step.
+        self.shared.write(level);
+        self.shared.changed.notify_all();
+    }
+
+    /// Move the level up or down by `delta`, saturating at 0 and 255.
+    pub fn step(&self, delta: i16) {
+        let level = (self.level() as i16 + delta).clamp(0, 255) as u8;
+        self.set(level);
+    }
+
+    /// Fade to `target` in the background, one level every `FADE_STEP_INTERVAL`. Retargets the
+    /// fade in progress, if any, so calling this often never piles up fades.
+    pub fn fade_to(&self, target: u8) {
+        self.shared.fade.lock().unwrap().target = Some(target);
+        self.shared.changed.notify_all();
+    }
+
+    /// Fade to `target` and wait until it's written, e.g. when the plugin is being disabled.
+    pub fn fade_to_blocking(&self, target: u8) {
+        let mut fade = self.shared.fade.lock().unwrap();
+        fade.target = Some(target);
+        self.shared.changed.notify_all();
+        let (_fade, result) = self
+            .shared
+            .changed
+            .wait_timeout_while(fade, FADE_TIMEOUT, |fade| fade.target.is_some())
+            .unwrap();
+        if result.timed_out() || !self.shared.writer.flush(FLUSH_TIMEOUT) {
+            plugin_debugln!("Timed out writing the backlight");
+        }
+    }
+}
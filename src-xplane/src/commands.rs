@@ -0,0 +1,178 @@
+use crate::backlight::Backlight;
+use crate::control::SharedControl;
+use crate::misc::{get_system_path, telemetry_dir};
+use crate::plugin_debugln;
+use crate::vibration::VibrationCommand;
+use xplm::command::{CommandHandler, OwnedCommand};
+
+/// How much one press of the gain up/down commands changes the master gain.
+const GAIN_STEP: f32 = 0.1;
+/// How much one press of the backlight up/down commands changes the brightness.
+const BACKLIGHT_STEP: i16 = 32;
+
+/// Flips vibration on/off.
+struct ToggleVibration {
+    control: SharedControl,
+}
+
+impl CommandHandler for ToggleVibration {
+    fn command_begin(&mut self) {
+        let mut control = self.control.borrow_mut();
+        let enabled = !control.enabled();
+        control.set_enabled(enabled);
+    }
+    fn command_continue(&mut self) {}
+    fn command_end(&mut self) {}
+}
+
+/// Steps the master gain once per press.
+struct StepGain {
+    control: SharedControl,
+    step: f32,
+}
+
+impl CommandHandler for StepGain {
+    fn command_begin(&mut self) {
+        let mut control = self.control.borrow_mut();
+        let gain = control.gain() + self.step;
+        control.set_gain(gain);
+    }
+    fn command_continue(&mut self) {}
+    fn command_end(&mut self) {}
+}
+
+/// Steps the backlight once per press.
+struct StepBacklight {
+    backlight: Backlight,
+    step: i16,
+}
+
+impl CommandHandler for StepBacklight {
+    fn command_begin(&mut self) {
+        self.backlight.step(self.step);
+    }
+    fn command_continue(&mut self) {}
+    fn command_end(&mut self) {}
+}
+
+/// Fades the backlight to a fixed level.
+struct FadeBacklight {
+    backlight: Backlight,
+    target: u8,
+}
+
+impl CommandHandler for FadeBacklight {
+    fn command_begin(&mut self) {
+        self.backlight.fade_to(self.target);
+    }
+    fn command_continue(&mut self) {}
+    fn command_end(&mut self) {}
+}
+
+/// Starts or stops the telemetry recording on the vibration worker.
+struct RecordTelemetry {
+    control: SharedControl,
+    start: bool,
+}
+
+impl CommandHandler for RecordTelemetry {
+    fn command_begin(&mut self) {
+        let command = if self.start {
+            VibrationCommand::StartRecording(telemetry_dir(&get_system_path()))
+        } else {
+            VibrationCommand::StopRecording
+        };
+        self.control.borrow().send(command);
+    }
+    fn command_continue(&mut self) {}
+    fn command_end(&mut self) {}
+}
+
+/// Register the plugin's commands under `xairline/ursa_minor/`. Commands that can't be created
+/// are logged and skipped; the returned commands stay registered for as long as they are kept.
+pub fn create_commands(control: &SharedControl, backlight: &Backlight) -> Vec<OwnedCommand> {
+    let commands = [
+        create(
+            "xairline/ursa_minor/vibration/toggle",
+            "Toggle stick vibration on/off",
+            ToggleVibration {
+                control: control.clone(),
+            },
+        ),
+        create(
+            "xairline/ursa_minor/vibration/gain_up",
+            "Increase stick vibration intensity",
+            StepGain {
+                control: control.clone(),
+                step: GAIN_STEP,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/vibration/gain_down",
+            "Decrease stick vibration intensity",
+            StepGain {
+                control: control.clone(),
+                step: -GAIN_STEP,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/backlight/up",
+            "Increase stick backlight brightness",
+            StepBacklight {
+                backlight: backlight.clone(),
+                step: BACKLIGHT_STEP,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/backlight/down",
+            "Decrease stick backlight brightness",
+            StepBacklight {
+                backlight: backlight.clone(),
+                step: -BACKLIGHT_STEP,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/backlight/fade_on",
+            "Fade the stick backlight to full brightness",
+            FadeBacklight {
+                backlight: backlight.clone(),
+                target: 255,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/backlight/fade_off",
+            "Fade the stick backlight off",
+            FadeBacklight {
+                backlight: backlight.clone(),
+                target: 0,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/telemetry/start",
+            "Start recording vibration telemetry",
+            RecordTelemetry {
+                control: control.clone(),
+                start: true,
+            },
+        ),
+        create(
+            "xairline/ursa_minor/telemetry/stop",
+            "Stop recording vibration telemetry",
+            RecordTelemetry {
+                control: control.clone(),
+                start: false,
+            },
+        ),
+    ];
+    commands.into_iter().flatten().collect()
+}
+
+fn create<H: CommandHandler>(name: &str, description: &str, handler: H) -> Option<OwnedCommand> {
+    match OwnedCommand::new(name, description, handler) {
+        Ok(command) => Some(command),
+        Err(e) => {
+            plugin_debugln!("Failed to create command {}: {}", name, e);
+            None
+        }
+    }
+}
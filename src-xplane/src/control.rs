@@ -0,0 +1,175 @@
+use crate::flight_loop::FlightLoopInterval;
+use crate::plugin_debugln;
+use crate::vibration::{CommandSender, VibrationCommand};
+use std::cell::RefCell;
+use std::rc::Rc;
+use xa_ursa_minor_hid::engine::{EngineParams, Tunable};
+use xa_ursa_minor_hid::profile::Profile;
+
+/// Smallest and largest master gain the commands can step to.
+pub const MIN_GAIN: f32 = 0.0;
+pub const MAX_GAIN: f32 = 2.0;
+
+/// User-facing vibration state, owned by the main thread and shared by the flight loop, the
+/// datarefs and the X-Plane commands. Every change is forwarded to the running vibration worker,
+/// and replayed to a new worker when the plugin is re-enabled.
+pub struct VibrationControl {
+    commands: Option<CommandSender>,
+    enabled: bool,
+    gain: f32,
+    /// Selectable profiles; never empty.
+    profiles: Vec<Profile>,
+    profile_index: usize,
+    params: EngineParams,
+    /// How often the flight loop samples the g-force datarefs. Deltas are normalized by the
+    /// actual time between samples, so this trades CPU time for latency without changing how
+    /// strong effects feel.
+    sample_interval: FlightLoopInterval,
+}
+
+pub type SharedControl = Rc<RefCell<VibrationControl>>;
+
+impl Default for VibrationControl {
+    fn default() -> Self {
+        Self {
+            commands: None,
+            enabled: true,
+            gain: 1.0,
+            profiles: vec![Profile::default()],
+            profile_index: 0,
+            params: EngineParams::default(),
+            sample_interval: FlightLoopInterval::Frames(1),
+        }
+    }
+}
+
+impl VibrationControl {
+    /// Shared control offering `profiles`, with the first one selected.
+    pub fn shared(profiles: Vec<Profile>) -> SharedControl {
+        let mut control = Self::default();
+        if !profiles.is_empty() {
+            control.profiles = profiles;
+        }
+        Rc::new(RefCell::new(control))
+    }
+
+    /// Route commands to a freshly started worker and bring it up to date.
+    pub fn connect(&mut self, commands: CommandSender) {
+        self.commands = Some(commands);
+        self.send(VibrationCommand::SetEnabled(self.enabled));
+        self.send(VibrationCommand::SetGain(self.gain));
+    }
+
+    /// Stop routing commands, e.g. when the worker is being stopped.
+    pub fn disconnect(&mut self) {
+        self.commands = None;
+    }
+
+    /// Whether a worker is running to receive commands.
+    pub fn connected(&self) -> bool {
+        self.commands.is_some()
+    }
+
+    /// Send a command to the worker. Dropped if no worker is running yet.
+    pub fn send(&self, command: VibrationCommand) {
+        let Some(commands) = &self.commands else {
+            return;
+        };
+        if let Err(e) = commands.send(command) {
+            plugin_debugln!("Failed to send vibration command: {}", e);
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled != self.enabled {
+            self.enabled = enabled;
+            self.send(VibrationCommand::SetEnabled(enabled));
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn profile_index(&self) -> usize {
+        self.profile_index
+    }
+
+    /// The selected profile, which a new worker should start with.
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.profile_index]
+    }
+
+    /// Select the profile at `index`. Out-of-range indices are ignored.
+    pub fn select_profile(&mut self, index: usize) {
+        if index < self.profiles.len() && index != self.profile_index {
+            self.profile_index = index;
+            self.send(VibrationCommand::SetProfile(self.profile().clone()));
+        }
+    }
+
+    /// Replace the profile with the same name, or add it, and select it. Used for live edits
+    /// from the desktop app, so the profile is sent to the worker even if nothing changed.
+    pub fn apply_profile(&mut self, profile: Profile) {
+        match self.profiles.iter().position(|p| p.name == profile.name) {
+            Some(index) => {
+                self.profiles[index] = profile;
+                self.profile_index = index;
+            }
+            None => {
+                self.profiles.push(profile);
+                self.profile_index = self.profiles.len() - 1;
+            }
+        }
+        self.send(VibrationCommand::SetProfile(self.profile().clone()));
+    }
+
+    /// Engine parameters a new worker should start with.
+    pub fn params(&self) -> &EngineParams {
+        &self.params
+    }
+
+    pub fn set_tunable(&mut self, tunable: Tunable, value: f32) {
+        if !value.is_finite() {
+            plugin_debugln!("Ignoring {} {}", tunable.name(), value);
+            return;
+        }
+        tunable.set(&mut self.params, value);
+        self.send(VibrationCommand::SetTunable(
+            tunable,
+            tunable.get(&self.params),
+        ));
+    }
+
+    pub fn sample_interval(&self) -> FlightLoopInterval {
+        self.sample_interval
+    }
+
+    /// Read by the flight loop when it schedules its next call.
+    pub fn set_sample_interval(&mut self, interval: FlightLoopInterval) {
+        self.sample_interval = interval;
+    }
+
+    /// Set the master gain applied to the motor output, clamped to `MIN_GAIN..=MAX_GAIN`.
+    /// Non-finite values are ignored: `clamp` would let NaN through.
+    pub fn set_gain(&mut self, gain: f32) {
+        if !gain.is_finite() {
+            plugin_debugln!("Ignoring vibration gain {}", gain);
+            return;
+        }
+        let gain = gain.clamp(MIN_GAIN, MAX_GAIN);
+        if gain != self.gain {
+            plugin_debugln!("Vibration gain -> {:.2}", gain);
+            self.gain = gain;
+            self.send(VibrationCommand::SetGain(gain));
+        }
+    }
+}
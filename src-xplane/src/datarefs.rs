@@ -0,0 +1,91 @@
+use crate::control::VibrationControl;
+use crate::plugin_debugln;
+use crate::vibration::VibrationCommand;
+use xa_ursa_minor_hid::pattern::Pattern;
+use xplm::data::owned::OwnedData;
+use xplm::data::{DataRead, DataReadWrite, ReadOnly, ReadWrite};
+
+const OVERRIDE_INTENSITY: &str = "xairline/ursa_minor/vibration/override_intensity";
+const PATTERN_TRIGGER: &str = "xairline/ursa_minor/vibration/pattern_trigger";
+const ENABLED: &str = "xairline/ursa_minor/vibration/enabled";
+const CURRENT_INTENSITY: &str = "xairline/ursa_minor/vibration/current_intensity";
+
+/// Datarefs we publish so third-party aircraft (Lua, plugins) can drive the stick motor:
+///   - `override_intensity` (float, 0..1): above zero, replaces every effect except the shaker.
+///   - `pattern_trigger` (int): write N >= 1 to play the N-th loaded pattern, or a negative value
+///     to stop the current one. Reset to 0 once handled.
+///   - `enabled` (int): 0 mutes the motor, 1 turns it back on. Also follows the toggle command.
+///   - `current_intensity` (float, 0..1, read-only): what the motor is doing right now.
+pub struct VibrationDataRefs {
+    override_intensity: OwnedData<f32, ReadWrite>,
+    pattern_trigger: OwnedData<i32, ReadWrite>,
+    enabled: OwnedData<i32, ReadWrite>,
+    current_intensity: OwnedData<f32, ReadOnly>,
+    last_override: Option<u8>,
+    last_enabled: bool,
+}
+
+impl VibrationDataRefs {
+    /// Register the datarefs. Fails if another plugin already owns one of the names.
+    pub fn create() -> Result<Self, String> {
+        Ok(Self {
+            override_intensity: OwnedData::create(OVERRIDE_INTENSITY)
+                .map_err(|e| create_error(OVERRIDE_INTENSITY, e))?,
+            pattern_trigger: OwnedData::create(PATTERN_TRIGGER)
+                .map_err(|e| create_error(PATTERN_TRIGGER, e))?,
+            enabled: OwnedData::create_with_value(ENABLED, &1)
+                .map_err(|e| create_error(ENABLED, e))?,
+            current_intensity: OwnedData::create(CURRENT_INTENSITY)
+                .map_err(|e| create_error(CURRENT_INTENSITY, e))?,
+            last_override: None,
+            last_enabled: true,
+        })
+    }
+
+    /// Called every flight loop: forward what other plugins wrote to the vibration worker,
+    /// and publish the motor intensity the worker last wrote.
+    pub fn update(
+        &mut self,
+        control: &mut VibrationControl,
+        patterns: &[Pattern],
+        current_intensity: u8,
+    ) {
+        let override_value = self.override_intensity.get();
+        let override_intensity = if override_value > 0.0 {
+            Some((override_value.min(1.0) * 255.0).round() as u8)
+        } else {
+            None
+        };
+        if override_intensity != self.last_override {
+            control.send(VibrationCommand::SetOverride(override_intensity));
+            self.last_override = override_intensity;
+        }
+
+        // A write by another plugin wins; otherwise mirror changes made through our commands.
+        let enabled = self.enabled.get() != 0;
+        if enabled != self.last_enabled {
+            control.set_enabled(enabled);
+        } else if control.enabled() != enabled {
+            self.enabled.set(control.enabled() as i32);
+        }
+        self.last_enabled = control.enabled();
+
+        let trigger = self.pattern_trigger.get();
+        if trigger != 0 {
+            if trigger < 0 {
+                control.send(VibrationCommand::StopPattern);
+            } else if let Some(pattern) = patterns.get(trigger as usize - 1) {
+                control.send(VibrationCommand::PlayPattern(pattern.clone()));
+            } else {
+                plugin_debugln!("No pattern #{} to trigger", trigger);
+            }
+            self.pattern_trigger.set(0);
+        }
+
+        self.current_intensity.set(current_intensity as f32 / 255.0);
+    }
+}
+
+fn create_error(name: &str, e: impl std::fmt::Display) -> String {
+    format!("Failed to create dataref {name}: {e}")
+}
@@ -1,36 +1,269 @@
+use crate::backlight::{Backlight, BacklightMode};
+use crate::control::SharedControl;
+use crate::datarefs::VibrationDataRefs;
+use crate::ipc::answer_calls;
 use crate::plugin_debugln;
-use std::sync::mpsc::Sender;
+use crate::sampler::SimSampler;
+use crate::vibration::VibrationCommand;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use xa_ursa_minor_hid::ipc::IpcServer;
+use xa_ursa_minor_hid::pattern::Pattern;
+use xa_ursa_minor_hid::ring::RingSender;
+use xa_ursa_minor_hid::sample::SimSample;
+use xa_ursa_minor_hid::writer::HidWriter;
 use xplm::data::borrowed::DataRef;
 use xplm::data::{DataRead, ReadOnly};
-use xplm::flight_loop::FlightLoopCallback;
+use xplm::flight_loop::{FlightLoopCallback, LoopState};
+
+/// How often the flight loop runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlightLoopInterval {
+    /// Every N frames (1 = every frame).
+    Frames(u32),
+    /// Every N seconds.
+    Seconds(f32),
+}
+
+/// Look up the profile's shaker datarefs in the loaded aircraft. Missing ones are logged and
+/// skipped.
+fn find_shaker_datarefs(names: &[String]) -> Vec<TriggerDataRef> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let dataref = TriggerDataRef::find(name);
+            if dataref.is_none() {
+                plugin_debugln!("Shaker dataref {} not available", name);
+            }
+            dataref
+        })
+        .collect()
+}
+
+/// Instrument brightness (0..1) followed by the backlight in `BacklightMode::Auto`.
+const INSTRUMENT_BRIGHTNESS: &str = "sim/cockpit/electrical/instrument_brightness";
+
+pub fn find_instrument_brightness() -> Option<DataRef<f32, ReadOnly>> {
+    match DataRef::find(INSTRUMENT_BRIGHTNESS) {
+        Ok(dataref) => Some(dataref),
+        Err(e) => {
+            plugin_debugln!("Instrument brightness not available: {}", e);
+            None
+        }
+    }
+}
+
+/// Trigger datarefs can be int or float; either way non-zero means active.
+enum TriggerDataRef {
+    Int(DataRef<i32, ReadOnly>),
+    Float(DataRef<f32, ReadOnly>),
+}
+
+impl TriggerDataRef {
+    fn find(name: &str) -> Option<Self> {
+        if let Ok(dataref) = DataRef::find(name) {
+            return Some(TriggerDataRef::Int(dataref));
+        }
+        DataRef::find(name).ok().map(TriggerDataRef::Float)
+    }
+
+    fn is_active(&self) -> bool {
+        match self {
+            TriggerDataRef::Int(dataref) => dataref.get() != 0,
+            TriggerDataRef::Float(dataref) => dataref.get() != 0.0,
+        }
+    }
+}
+
+/// Fires a pattern when its trigger dataref goes from zero to non-zero.
+pub struct PatternTrigger {
+    dataref: TriggerDataRef,
+    pattern: Pattern,
+    was_active: bool,
+}
+
+/// Build a trigger for every pattern that names a trigger dataref which exists.
+pub fn find_pattern_triggers(patterns: &[Pattern]) -> Vec<PatternTrigger> {
+    patterns
+        .iter()
+        .filter_map(|pattern| {
+            let name = pattern.trigger.as_deref()?;
+            let Some(dataref) = TriggerDataRef::find(name) else {
+                plugin_debugln!(
+                    "Trigger dataref {} for pattern \"{}\" not available",
+                    name,
+                    pattern.name
+                );
+                return None;
+            };
+            // Start from the current state so a dataref that is already set doesn't fire.
+            let was_active = dataref.is_active();
+            Some(PatternTrigger {
+                dataref,
+                pattern: pattern.clone(),
+                was_active,
+            })
+        })
+        .collect()
+}
+
 pub struct FlightLoopHandler {
-    pub(crate) g_force_y: DataRef<f32, ReadOnly>,
-    pub(crate) g_force_x: DataRef<f32, ReadOnly>,
-    pub(crate) g_force_z: DataRef<f32, ReadOnly>,
-    pub(crate) last_g_force_y: f32,
-    pub(crate) last_g_force_x: f32,
-    pub(crate) last_g_force_z: f32,
-    pub(crate) tx: Sender<(f32, f32, f32)>,
+    sampler: SimSampler,
+    /// Names the shaker datarefs were resolved from, to notice when the profile changes them.
+    shaker_names: Vec<String>,
+    shaker_datarefs: Vec<TriggerDataRef>,
+    shaker_active: bool,
+    pattern_triggers: Vec<PatternTrigger>,
+    /// Loaded patterns, addressable through the `pattern_trigger` dataref.
+    patterns: Vec<Pattern>,
+    /// Our published datarefs; `None` if they couldn't be registered.
+    datarefs: Option<VibrationDataRefs>,
+    current_intensity: Arc<AtomicU8>,
+    /// Samples for the running worker; `None` while the plugin is disabled.
+    tx: Option<RingSender<SimSample>>,
+    /// Requests from the desktop app, served for the plugin's whole lifetime; `None` if the
+    /// server failed to start.
+    ipc: Option<IpcServer>,
+    control: SharedControl,
+    backlight: Backlight,
+    instrument_brightness: Option<DataRef<f32, ReadOnly>>,
+    /// Backlight level last set from the instrument brightness, so we only write on changes.
+    last_auto_backlight: Option<u8>,
 }
+
+impl FlightLoopHandler {
+    /// Look up every dataref the flight loop reads and register the ones we publish.
+    /// Fails if a sampled dataref is missing; optional datarefs are logged and skipped.
+    pub fn new(
+        control: SharedControl,
+        backlight: Backlight,
+        current_intensity: Arc<AtomicU8>,
+        ipc: Option<IpcServer>,
+    ) -> Result<Self, String> {
+        let datarefs = match VibrationDataRefs::create() {
+            Ok(datarefs) => Some(datarefs),
+            Err(e) => {
+                plugin_debugln!("{}", e);
+                None
+            }
+        };
+        Ok(Self {
+            sampler: SimSampler::new()?,
+            shaker_names: Vec::new(),
+            shaker_datarefs: Vec::new(),
+            shaker_active: false,
+            pattern_triggers: Vec::new(),
+            patterns: Vec::new(),
+            datarefs,
+            current_intensity,
+            tx: None,
+            ipc,
+            control,
+            backlight,
+            instrument_brightness: find_instrument_brightness(),
+            last_auto_backlight: None,
+        })
+    }
+
+    /// Start feeding a freshly started worker. Edge state is reset so the new worker gets the
+    /// current shaker state.
+    pub fn connect(&mut self, tx: RingSender<SimSample>, patterns: Vec<Pattern>) {
+        self.shaker_active = false;
+        self.last_auto_backlight = None;
+        self.patterns = patterns;
+        self.tx = Some(tx);
+        self.resolve_aircraft_datarefs();
+    }
+
+    /// Look up the shaker and pattern trigger datarefs again, e.g. once an aircraft is loaded
+    /// and its plugins have registered their own datarefs.
+    pub fn resolve_aircraft_datarefs(&mut self) {
+        self.resolve_shaker_datarefs();
+        self.pattern_triggers = find_pattern_triggers(&self.patterns);
+    }
+
+    /// Look up the shaker datarefs the selected profile names.
+    fn resolve_shaker_datarefs(&mut self) {
+        self.shaker_names = self.control.borrow().profile().shaker_datarefs.clone();
+        self.shaker_datarefs = find_shaker_datarefs(&self.shaker_names);
+    }
+
+    /// Stop sending samples; the worker sees its channel close.
+    pub fn disconnect(&mut self) {
+        self.tx = None;
+    }
+
+    /// Answer the desktop app. Runs from its own flight loop, so the app is served while the
+    /// plugin is disabled too.
+    pub fn answer_ipc(&self, writer: &HidWriter) {
+        if let Some(server) = &self.ipc {
+            answer_calls(
+                server,
+                &self.control,
+                &self.backlight,
+                &self.patterns,
+                self.current_intensity.load(Ordering::Relaxed),
+                writer.serial_number(),
+            );
+        }
+    }
+}
+
 impl FlightLoopCallback for FlightLoopHandler {
-    fn flight_loop(&mut self, _state: &mut xplm::flight_loop::LoopState) {
-        let cur_y = self.g_force_y.get();
-        let cur_x = self.g_force_x.get();
-        let cur_z = self.g_force_z.get();
+    fn flight_loop(&mut self, state: &mut LoopState) {
+        // Send the sample to the worker thread, which derives and normalizes the deltas.
+        let sample = self.sampler.sample(state.since_last_call().as_secs_f32());
+        if let Some(tx) = &self.tx {
+            if tx.send(sample).is_err() {
+                plugin_debugln!("Failed to send sim sample: the vibration worker stopped");
+            }
+        }
 
-        // Example of acceleration deltas:
-        let diff_y = cur_y - self.last_g_force_y;
-        let diff_x = cur_x - self.last_g_force_x;
-        let diff_z = cur_z - self.last_g_force_z;
+        if self.control.borrow().profile().shaker_datarefs != self.shaker_names {
+            self.resolve_shaker_datarefs();
+        }
+
+        // Only tell the worker when the shaker state flips, so it can start/stop crisply.
+        let shaker_active = self.shaker_datarefs.iter().any(TriggerDataRef::is_active);
+        if shaker_active != self.shaker_active {
+            self.control
+                .borrow()
+                .send(VibrationCommand::StickShaker(shaker_active));
+            self.shaker_active = shaker_active;
+        }
 
-        // Send deltas (or raw forces) to worker thread
-        // For raw, you'd just do (cur_x, cur_y, cur_z).
-        if let Err(e) = self.tx.send((diff_x, diff_y, diff_z)) {
-            plugin_debugln!("Failed to send g-force data: {}", e);
+        for trigger in self.pattern_triggers.iter_mut() {
+            let active = trigger.dataref.is_active();
+            if active && !trigger.was_active {
+                let command = VibrationCommand::PlayPattern(trigger.pattern.clone());
+                self.control.borrow().send(command);
+            }
+            trigger.was_active = active;
         }
 
-        self.last_g_force_y = self.g_force_y.get();
-        self.last_g_force_x = self.g_force_x.get();
-        self.last_g_force_z = self.g_force_z.get();
+        match (&self.instrument_brightness, self.backlight.mode()) {
+            (Some(brightness), BacklightMode::Auto) => {
+                let level = (brightness.get().clamp(0.0, 1.0) * 255.0).round() as u8;
+                if self.last_auto_backlight != Some(level) {
+                    self.backlight.fade_to(level);
+                    self.last_auto_backlight = Some(level);
+                }
+            }
+            _ => self.last_auto_backlight = None,
+        }
+
+        let intensity = self.current_intensity.load(Ordering::Relaxed);
+        if let Some(datarefs) = &mut self.datarefs {
+            datarefs.update(&mut self.control.borrow_mut(), &self.patterns, intensity);
+        }
+
+        let interval = self.control.borrow().sample_interval();
+        match interval {
+            FlightLoopInterval::Frames(frames) => state.call_next_after_loops(frames.max(1)),
+            FlightLoopInterval::Seconds(seconds) => {
+                state.call_next_after(Duration::from_secs_f32(seconds.max(0.001)))
+            }
+        }
     }
 }
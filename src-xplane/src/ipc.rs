@@ -0,0 +1,88 @@
+use crate::backlight::Backlight;
+use crate::control::SharedControl;
+use crate::plugin_debugln;
+use crate::vibration::VibrationCommand;
+use xa_ursa_minor_hid::ipc::{default_endpoint, IpcRequest, IpcResponse, IpcServer, PluginStatus};
+use xa_ursa_minor_hid::pattern::Pattern;
+
+/// Answered to pattern requests while no vibration worker runs.
+const VIBRATION_DISABLED: &str = "The X-Plane plugin is disabled";
+
+/// Serve the desktop app for as long as the plugin is loaded. The plugin works without it, so a
+/// failure is only logged.
+pub fn start_ipc_server() -> Option<IpcServer> {
+    let endpoint = default_endpoint();
+    match IpcServer::start(&endpoint, |e| plugin_debugln!("{}", e)) {
+        Ok(server) => {
+            plugin_debugln!("Serving the desktop app on {}", endpoint.display());
+            Some(server)
+        }
+        Err(e) => {
+            plugin_debugln!("{}", e);
+            None
+        }
+    }
+}
+
+/// Answer every request the app sent since the last call. Runs on the main thread, which
+/// owns the vibration control. The plugin owns the device while it serves the app, so the app's
+/// own device commands arrive here too.
+pub fn answer_calls(
+    server: &IpcServer,
+    control: &SharedControl,
+    backlight: &Backlight,
+    patterns: &[Pattern],
+    intensity: u8,
+    serial_number: Option<String>,
+) {
+    for call in server.calls() {
+        let response = match &call.request {
+            IpcRequest::Status => {
+                let control = control.borrow();
+                IpcResponse::Status {
+                    status: PluginStatus {
+                        enabled: control.enabled(),
+                        gain: control.gain(),
+                        profile: control.profile().name.clone(),
+                        profiles: control.profiles().iter().map(|p| p.name.clone()).collect(),
+                        patterns: patterns.iter().map(|p| p.name.clone()).collect(),
+                        intensity,
+                        serial_number: serial_number.clone(),
+                    },
+                }
+            }
+            IpcRequest::Intensity => IpcResponse::Intensity { intensity },
+            IpcRequest::SetProfile { profile } => {
+                plugin_debugln!("Profile \"{}\" updated from the desktop app", profile.name);
+                control.borrow_mut().apply_profile(profile.clone());
+                IpcResponse::Ok
+            }
+            IpcRequest::PlayPattern { .. } | IpcRequest::StopPattern
+                if !control.borrow().connected() =>
+            {
+                IpcResponse::Error {
+                    message: VIBRATION_DISABLED.to_string(),
+                }
+            }
+            IpcRequest::PlayPattern { pattern } => {
+                control
+                    .borrow()
+                    .send(VibrationCommand::PlayPattern(pattern.clone()));
+                IpcResponse::Ok
+            }
+            IpcRequest::StopPattern => {
+                control.borrow().send(VibrationCommand::StopPattern);
+                IpcResponse::Ok
+            }
+            IpcRequest::SetBacklight { level } => {
+                backlight.set(*level);
+                IpcResponse::Ok
+            }
+            // The connection answers the handshake itself.
+            IpcRequest::Hello { .. } => IpcResponse::Error {
+                message: "Already said hello".to_string(),
+            },
+        };
+        call.reply(response);
+    }
+}
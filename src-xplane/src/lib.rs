@@ -3,10 +3,19 @@ extern crate xplm;
 
 use xplm::xplane_plugin;
 
+mod backlight;
+mod commands;
+mod control;
+mod datarefs;
 mod flight_loop;
+mod ipc;
 mod logger;
+mod menu;
 mod misc;
 mod plugin;
+mod sampler;
+mod settings;
+mod telemetry;
 mod vibration;
 
 xplane_plugin!(plugin::UrsaMinorPlugin);
@@ -0,0 +1,244 @@
+use crate::backlight::{Backlight, BacklightMode};
+use crate::control::SharedControl;
+use crate::plugin_debugln;
+use crate::settings::SettingsWindow;
+use crate::vibration::VibrationCommand;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use xa_ursa_minor_hid::writer::HidWriter;
+use xplm::flight_loop::{FlightLoop, LoopState};
+use xplm::menu::{ActionItem, CheckHandler, CheckItem, Menu, MenuClickHandler, Separator};
+
+/// How often the check marks are brought in line with state changed elsewhere
+/// (commands, datarefs, the settings window).
+const SYNC_INTERVAL: Duration = Duration::from_millis(250);
+
+const BACKLIGHT_MODES: [(&str, BacklightMode); 3] = [
+    ("On", BacklightMode::On),
+    ("Off", BacklightMode::Off),
+    ("Auto (instrument brightness)", BacklightMode::Auto),
+];
+
+/// Items whose check marks mirror plugin state.
+struct CheckMarks {
+    enabled: Option<Rc<CheckItem>>,
+    profiles: Vec<Rc<CheckItem>>,
+    backlight: Vec<(Rc<CheckItem>, BacklightMode)>,
+}
+
+impl CheckMarks {
+    fn sync(&self, control: &SharedControl, backlight: &Backlight) {
+        let control = control.borrow();
+        if let Some(item) = &self.enabled {
+            item.set_checked(control.enabled());
+        }
+        for (index, item) in self.profiles.iter().enumerate() {
+            item.set_checked(index == control.profile_index());
+        }
+        let mode = backlight.mode();
+        for (item, item_mode) in &self.backlight {
+            item.set_checked(*item_mode == mode);
+        }
+    }
+}
+
+/// The "XA URSA Minor" submenu in the Plugins menu:
+///   - Vibration enabled (check)
+///   - Profile > one check item per profile
+///   - Backlight > On / Off / Auto
+///   - Reconnect device
+///   - Settings...
+pub struct PluginMenu {
+    /// Keeps the check marks in sync while the plugin is loaded, and owns the menu.
+    _sync_loop: FlightLoop,
+}
+
+/// The menu as built for one list of profiles.
+struct BuiltMenu {
+    menu: Menu,
+    check_marks: CheckMarks,
+    /// Names the profile items were created for, to notice profiles added later.
+    profile_names: Vec<String>,
+}
+
+fn profile_names(control: &SharedControl) -> Vec<String> {
+    control
+        .borrow()
+        .profiles()
+        .iter()
+        .map(|profile| profile.name.clone())
+        .collect()
+}
+
+pub fn create_menu(
+    control: &SharedControl,
+    backlight: &Backlight,
+    writer: &Arc<HidWriter>,
+    settings: &Rc<RefCell<SettingsWindow>>,
+) -> Option<PluginMenu> {
+    let mut built = build_menu(control, backlight, writer, settings)?;
+    let control = control.clone();
+    let backlight = backlight.clone();
+    let writer = Arc::clone(writer);
+    let settings = Rc::clone(settings);
+    let mut sync_loop = FlightLoop::new(move |loop_state: &mut LoopState| {
+        // Profiles added from the desktop app need items of their own, and shift the indices
+        // the check marks go by, so the menu is built again.
+        let names = profile_names(&control);
+        if names != built.profile_names {
+            match build_menu(&control, &backlight, &writer, &settings) {
+                Some(rebuilt) => {
+                    built.menu.remove_from_plugins_menu();
+                    built = rebuilt;
+                }
+                // Keep the old menu; don't retry until the profiles change again.
+                None => built.profile_names = names,
+            }
+        }
+        built.check_marks.sync(&control, &backlight);
+        loop_state.call_next_after(SYNC_INTERVAL);
+    });
+    sync_loop.schedule_immediate();
+
+    Some(PluginMenu {
+        _sync_loop: sync_loop,
+    })
+}
+
+/// Build the menu for the current profiles and add it to the Plugins menu.
+fn build_menu(
+    control: &SharedControl,
+    backlight: &Backlight,
+    writer: &Arc<HidWriter>,
+    settings: &Rc<RefCell<SettingsWindow>>,
+) -> Option<BuiltMenu> {
+    let menu = match Menu::new("XA URSA Minor") {
+        Ok(menu) => menu,
+        Err(e) => {
+            plugin_debugln!("Failed to create menu: {}", e);
+            return None;
+        }
+    };
+
+    let enabled = {
+        let checked = control.borrow().enabled();
+        let control = control.clone();
+        check_item(
+            "Vibration enabled",
+            checked,
+            move |_: &CheckItem, checked: bool| control.borrow_mut().set_enabled(checked),
+        )
+    };
+    if let Some(item) = &enabled {
+        menu.add_child(Rc::clone(item));
+    }
+
+    let profile_menu = submenu("Profile");
+    let mut profiles = Vec::new();
+    let names = profile_names(control);
+    if let Some(profile_menu) = &profile_menu {
+        for (index, name) in names.iter().enumerate() {
+            let control = control.clone();
+            let selected = index == control.borrow().profile_index();
+            if let Some(item) = check_item(name, selected, move |_: &CheckItem, _: bool| {
+                control.borrow_mut().select_profile(index)
+            }) {
+                profile_menu.add_child(Rc::clone(&item));
+                profiles.push(item);
+            }
+        }
+        menu.add_child(Rc::clone(profile_menu));
+    }
+
+    let backlight_menu = submenu("Backlight");
+    let mut backlight_items = Vec::new();
+    if let Some(backlight_menu) = &backlight_menu {
+        for (name, mode) in BACKLIGHT_MODES {
+            let backlight = backlight.clone();
+            let selected = backlight.mode() == mode;
+            if let Some(item) = check_item(name, selected, move |_: &CheckItem, _: bool| {
+                backlight.set_mode(mode)
+            }) {
+                backlight_menu.add_child(Rc::clone(&item));
+                backlight_items.push((item, mode));
+            }
+        }
+        menu.add_child(Rc::clone(backlight_menu));
+    }
+
+    {
+        let control = control.clone();
+        let backlight = backlight.clone();
+        let writer = Arc::clone(writer);
+        if let Some(item) = action_item("Reconnect device", move |_: &ActionItem| {
+            reconnect_device(&control, &backlight, &writer)
+        }) {
+            menu.add_child(item);
+        }
+    }
+
+    menu.add_child(Separator);
+    {
+        let settings = Rc::clone(settings);
+        if let Some(item) = action_item("Settings...", move |_: &ActionItem| {
+            settings.borrow_mut().show()
+        }) {
+            menu.add_child(item);
+        }
+    }
+    menu.add_to_plugins_menu();
+
+    Some(BuiltMenu {
+        menu,
+        check_marks: CheckMarks {
+            enabled,
+            profiles,
+            backlight: backlight_items,
+        },
+        profile_names: names,
+    })
+}
+
+/// Reopen the device, reset the motor and restore the backlight level.
+fn reconnect_device(control: &SharedControl, backlight: &Backlight, writer: &HidWriter) {
+    plugin_debugln!("Reconnecting device");
+    writer.reopen();
+    control.borrow().send(VibrationCommand::Reconnect);
+    backlight.set(backlight.level());
+}
+
+fn submenu(name: &str) -> Option<Rc<Menu>> {
+    match Menu::new(name) {
+        Ok(menu) => Some(Rc::new(menu)),
+        Err(e) => {
+            plugin_debugln!("Failed to create menu {}: {}", name, e);
+            None
+        }
+    }
+}
+
+fn check_item<S: AsRef<str>, H: CheckHandler>(
+    name: S,
+    checked: bool,
+    handler: H,
+) -> Option<Rc<CheckItem>> {
+    match CheckItem::new(name.as_ref(), checked, handler) {
+        Ok(item) => Some(Rc::new(item)),
+        Err(e) => {
+            plugin_debugln!("Failed to create menu item {}: {}", name.as_ref(), e);
+            None
+        }
+    }
+}
+
+fn action_item<H: MenuClickHandler>(name: &str, handler: H) -> Option<Rc<ActionItem>> {
+    match ActionItem::new(name, handler) {
+        Ok(item) => Some(Rc::new(item)),
+        Err(e) => {
+            plugin_debugln!("Failed to create menu item {}: {}", name, e);
+            None
+        }
+    }
+}
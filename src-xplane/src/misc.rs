@@ -21,7 +21,40 @@ pub fn get_system_path() -> String {
 
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Directory holding the vibration profiles: `<X-Plane>/Output/preferences/xa-ursa-minor`.
+pub fn profiles_dir(system_path: &str) -> PathBuf {
+    Path::new(system_path)
+        .join("Output")
+        .join("preferences")
+        .join("xa-ursa-minor")
+}
+
+/// Directory holding the haptic patterns: `<X-Plane>/Output/preferences/xa-ursa-minor/patterns`.
+pub fn patterns_dir(system_path: &str) -> PathBuf {
+    profiles_dir(system_path).join("patterns")
+}
+
+/// Directory telemetry recordings are written to: `<X-Plane>/Output/xa-ursa-minor-telemetry`.
+pub fn telemetry_dir(system_path: &str) -> PathBuf {
+    Path::new(system_path)
+        .join("Output")
+        .join("xa-ursa-minor-telemetry")
+}
+
+/// Every `*.json` file in `dir`, sorted by file name. Empty if the directory doesn't exist.
+pub fn json_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths
+}
 
 pub fn read_xplane_preferences(system_path: &str) -> Result<bool, io::Error> {
     // Construct the full path to the preferences file.
@@ -43,4 +76,4 @@ pub fn read_xplane_preferences(system_path: &str) -> Result<bool, io::Error> {
     }
 
     Ok(false) // Return false if the condition is not met.
-}
\ No newline at end of file
+}
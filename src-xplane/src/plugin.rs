@@ -1,85 +1,232 @@
+use crate::backlight::Backlight;
+use crate::commands::create_commands;
+use crate::control::{SharedControl, VibrationControl};
 use crate::flight_loop::FlightLoopHandler;
+use crate::ipc::start_ipc_server;
+use crate::menu::{create_menu, PluginMenu};
+use crate::misc::{get_system_path, json_files, patterns_dir, profiles_dir};
 use crate::plugin_debugln;
-use crate::vibration::start_vibration_thread;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use crate::settings::SettingsWindow;
+use crate::vibration::{
+    start_vibration_thread, CommandSender, VibrationWorker, MAX_SAMPLE_AGE, SAMPLE_CAPACITY,
+};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::AtomicU8;
+use std::sync::{mpsc, Arc};
 use std::time::Duration;
 use xa_ursa_minor_hid::hid::HIDWrapper;
-use xplm::data::borrowed::DataRef;
-use xplm::flight_loop::FlightLoop;
+use xa_ursa_minor_hid::pattern::Pattern;
+use xa_ursa_minor_hid::profile::Profile;
+use xa_ursa_minor_hid::ring::ring_channel;
+use xa_ursa_minor_hid::writer::{HidWriter, DEFAULT_WRITE_TIMEOUT};
+use xplm::command::OwnedCommand;
+use xplm::flight_loop::{FlightLoop, FlightLoopCallback, LoopState};
 use xplm::plugin::{Plugin, PluginInfo};
+use xplm_sys::XPLM_MSG_PLANE_LOADED;
+
+/// How often requests from the desktop app are answered.
+const IPC_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Profile selected on start, from `profiles_dir`.
+const DEFAULT_PROFILE_FILE: &str = "default.json";
+
+/// Load the selectable profiles from X-Plane's preferences. The first one is selected on start:
+/// `default.json` if there is one, the built-in profile otherwise. Invalid files are logged and
+/// skipped.
+fn load_profiles() -> Vec<Profile> {
+    let mut profiles = vec![Profile::default()];
+    for path in json_files(&profiles_dir(&get_system_path())) {
+        match Profile::load(&path) {
+            Ok(profile) => {
+                plugin_debugln!("Loaded vibration profile \"{}\"", profile.name);
+                if path
+                    .file_name()
+                    .is_some_and(|name| name == DEFAULT_PROFILE_FILE)
+                {
+                    profiles[0] = profile;
+                } else {
+                    profiles.push(profile);
+                }
+            }
+            Err(e) => plugin_debugln!("Skipping profile {}: {}", path.display(), e),
+        }
+    }
+    profiles
+}
+
+/// Load every `*.json` pattern from `patterns_dir`, sorted by file name so the
+/// `pattern_trigger` dataref numbers are stable. Invalid files are logged and skipped.
+fn load_patterns() -> Vec<Pattern> {
+    json_files(&patterns_dir(&get_system_path()))
+        .into_iter()
+        .filter_map(|path| match Pattern::load(&path) {
+            Ok(pattern) => {
+                plugin_debugln!("Loaded pattern \"{}\"", pattern.name);
+                Some(pattern)
+            }
+            Err(e) => {
+                plugin_debugln!("Skipping pattern {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Why the plugin failed to start.
+#[derive(Debug)]
+pub struct PluginError(String);
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// Lifecycle of the plugin, as driven by X-Plane:
+/// `Started` after `start`, then `Enabled` and `Disabled` as the user toggles the plugin.
+enum PluginState {
+    /// Loaded; nothing running yet.
+    Started,
+    /// The flight loop is scheduled and feeds this worker.
+    Enabled(VibrationWorker),
+    /// The flight loop is deactivated and the worker stopped.
+    Disabled,
+}
 
 pub struct UrsaMinorPlugin {
+    state: PluginState,
+    /// Registered once on start and scheduled while enabled.
     flight_loop: FlightLoop,
-    hidwrapper: Arc<Mutex<HIDWrapper>>,
+    /// Answers the desktop app from start to unload, whether or not the plugin is enabled.
+    _ipc_loop: FlightLoop,
+    /// Shared with the flight loop so each new worker can be connected to it.
+    handler: Rc<RefCell<FlightLoopHandler>>,
+    control: SharedControl,
+    backlight: Backlight,
+    /// Owns the device for writing, shared with each new worker.
+    writer: Arc<HidWriter>,
+    /// Last motor intensity written by the worker, shared across restarts of the worker.
+    current_intensity: Arc<AtomicU8>,
+    /// Kept so the commands stay registered while the plugin is loaded.
+    _commands: Vec<OwnedCommand>,
+    _settings: Rc<RefCell<SettingsWindow>>,
+    _menu: Option<PluginMenu>,
 }
 
 impl Plugin for UrsaMinorPlugin {
-    type Error = std::convert::Infallible;
+    type Error = PluginError;
 
     fn start() -> Result<Self, Self::Error> {
         // The following message should be visible in the developer console and the Log.txt file
         plugin_debugln!("Hello, World! From the Minimal Rust Plugin");
 
-        let (tx, r_) = std::sync::mpsc::channel();
-        let plugin = Self {
-            hidwrapper: Arc::new(Mutex::new(HIDWrapper::new().unwrap())),
-            flight_loop: FlightLoop::new(FlightLoopHandler {
-                g_force_y: DataRef::find("sim/flightmodel2/misc/gforce_axil").unwrap(),
-                g_force_x: DataRef::find("sim/flightmodel2/misc/gforce_side").unwrap(),
-                g_force_z: DataRef::find("sim/flightmodel2/misc/gforce_normal").unwrap(),
-                last_g_force_x: 0.0,
-                last_g_force_y: 0.0,
-                last_g_force_z: 0.0,
-                tx: tx,
-            }),
-        };
+        let open =
+            || HIDWrapper::new().ok_or_else(|| "Failed to initialise the HID API".to_string());
+        let writer = HidWriter::start(open, DEFAULT_WRITE_TIMEOUT, |e| plugin_debugln!("{}", e))
+            .map_err(PluginError)?;
+        let writer = Arc::new(writer);
+        let backlight = Backlight::new(Arc::clone(&writer));
+        let control = VibrationControl::shared(load_profiles());
+        let current_intensity = Arc::new(AtomicU8::new(0));
+
+        let handler = FlightLoopHandler::new(
+            control.clone(),
+            backlight.clone(),
+            Arc::clone(&current_intensity),
+            start_ipc_server(),
+        )
+        .map_err(PluginError)?;
+        let handler = Rc::new(RefCell::new(handler));
+        let loop_handler = Rc::clone(&handler);
+        let flight_loop = FlightLoop::new(move |loop_state: &mut LoopState| {
+            loop_handler.borrow_mut().flight_loop(loop_state)
+        });
+        let ipc_handler = Rc::clone(&handler);
+        let ipc_writer = Arc::clone(&writer);
+        let mut ipc_loop = FlightLoop::new(move |loop_state: &mut LoopState| {
+            ipc_handler.borrow().answer_ipc(&ipc_writer);
+            loop_state.call_next_after(IPC_INTERVAL);
+        });
+        ipc_loop.schedule_immediate();
+
+        let settings = Rc::new(RefCell::new(SettingsWindow::new(
+            control.clone(),
+            backlight.clone(),
+            Arc::clone(&writer),
+            Arc::clone(&current_intensity),
+        )));
+        let menu = create_menu(&control, &backlight, &writer, &settings);
 
-        Ok(plugin)
+        Ok(Self {
+            state: PluginState::Started,
+            flight_loop,
+            _ipc_loop: ipc_loop,
+            handler,
+            _commands: create_commands(&control, &backlight),
+            _menu: menu,
+            _settings: settings,
+            control,
+            backlight,
+            writer,
+            current_intensity,
+        })
     }
 
     fn enable(&mut self) -> Result<(), Self::Error> {
+        if let PluginState::Enabled(_) = self.state {
+            return Ok(());
+        }
         // The following message should be visible in the developer console and the Log.txt file
         plugin_debugln!("Plugin enabled");
-        let (tx, rx) = std::sync::mpsc::channel();
-        start_vibration_thread(rx);
-        self.flight_loop = FlightLoop::new(FlightLoopHandler {
-            g_force_y: DataRef::find("sim/flightmodel2/misc/gforce_axil").unwrap(),
-            g_force_x: DataRef::find("sim/flightmodel2/misc/gforce_side").unwrap(),
-            g_force_z: DataRef::find("sim/flightmodel2/misc/gforce_normal").unwrap(),
-            last_g_force_x: 0.0,
-            last_g_force_y: 0.0,
-            last_g_force_z: 0.0,
-            tx: tx,
-        });
-        // Clone the Arc pointer (cheap operation) for sharing with the spawned thread.
-        let hidwrapper_shared = Arc::clone(&self.hidwrapper);
-        thread::spawn(move || {
-            for i in 0..=255 {
-                {
-                    // Lock the mutex to safely use the HIDWrapper.
-                    let mut hw = hidwrapper_shared.lock().unwrap();
-                    hw.write_backlight(i).unwrap();
-                }
-                thread::sleep(Duration::from_millis(5));
-            }
-        });
+
+        let (tx, rx) = ring_channel(SAMPLE_CAPACITY, MAX_SAMPLE_AGE);
+        let (command_tx, command_rx) = mpsc::channel();
+        let commands = CommandSender::new(command_tx, rx.waker());
+        let worker = start_vibration_thread(
+            rx,
+            command_rx,
+            Arc::clone(&self.writer),
+            self.control.borrow().profile().clone(),
+            self.control.borrow().params().clone(),
+            Arc::clone(&self.current_intensity),
+        );
+        self.control.borrow_mut().connect(commands);
+        self.handler.borrow_mut().connect(tx, load_patterns());
+        self.state = PluginState::Enabled(worker);
+
+        if let Some(level) = self.backlight.mode_level() {
+            self.backlight.fade_to(level);
+        }
         self.flight_loop.schedule_immediate();
         Ok(())
     }
 
     fn disable(&mut self) {
+        let PluginState::Enabled(worker) =
+            std::mem::replace(&mut self.state, PluginState::Disabled)
+        else {
+            return;
+        };
+        plugin_debugln!("Plugin disabled");
+
         self.flight_loop.deactivate();
-        HIDWrapper::new().unwrap().write_vibration(0).unwrap();
-        // Clone the Arc pointer (cheap operation) for sharing with the spawned thread.
-        let hidwrapper_shared = Arc::clone(&self.hidwrapper);
-        for i in 0..=255 {
-            {
-                // Lock the mutex to safely use the HIDWrapper.
-                let mut hw = hidwrapper_shared.lock().unwrap();
-                hw.write_backlight(255 - i).unwrap();
-                thread::sleep(Duration::from_millis(5));
-            }
+        self.handler.borrow_mut().disconnect();
+        self.control.borrow_mut().disconnect();
+        // The worker zeroes the motor before it exits.
+        worker.stop();
+        self.backlight.fade_to_blocking(0);
+    }
+
+    fn receive_message(&mut self, _from: i32, message: i32, param: *mut c_void) {
+        // A null parameter is the user's aircraft; AI aircraft don't matter to the stick.
+        if message as u32 == XPLM_MSG_PLANE_LOADED && param.is_null() {
+            plugin_debugln!("Aircraft loaded, looking up its datarefs");
+            self.handler.borrow_mut().resolve_aircraft_datarefs();
         }
     }
 
@@ -0,0 +1,76 @@
+use xa_ursa_minor_hid::sample::SimSample;
+use xplm::data::borrowed::DataRef;
+use xplm::data::{ArrayRead, DataRead, DataType, ReadOnly};
+
+/// Number of gear legs read from the deploy ratio array.
+const GEAR_LEGS: usize = 10;
+
+/// Every sim view type (forward with panel, 3D cockpit, chase, ...) is 1000 or above. Anything
+/// lower means no sim view is shown, as while a full-screen menu is open.
+const FIRST_SIM_VIEW_TYPE: i32 = 1000;
+
+/// Look up a dataref the sampler can't work without.
+fn find<T: DataType + ?Sized>(name: &str) -> Result<DataRef<T, ReadOnly>, String> {
+    DataRef::find(name).map_err(|e| format!("Failed to find dataref {name}: {e}"))
+}
+
+/// Reads a `SimSample` from the sim's datarefs.
+pub struct SimSampler {
+    sim_time: DataRef<f32, ReadOnly>,
+    g_force_x: DataRef<f32, ReadOnly>,
+    g_force_y: DataRef<f32, ReadOnly>,
+    g_force_z: DataRef<f32, ReadOnly>,
+    on_ground: DataRef<i32, ReadOnly>,
+    groundspeed: DataRef<f32, ReadOnly>,
+    indicated_airspeed: DataRef<f32, ReadOnly>,
+    angle_of_attack: DataRef<f32, ReadOnly>,
+    gear_deploy: DataRef<[f32], ReadOnly>,
+    flap_deploy: DataRef<f32, ReadOnly>,
+    paused: DataRef<i32, ReadOnly>,
+    replay: DataRef<i32, ReadOnly>,
+    view_type: DataRef<i32, ReadOnly>,
+}
+
+impl SimSampler {
+    pub fn new() -> Result<Self, String> {
+        Ok(Self {
+            sim_time: find("sim/time/total_running_time_sec")?,
+            g_force_x: find("sim/flightmodel2/misc/gforce_side")?,
+            g_force_y: find("sim/flightmodel2/misc/gforce_axil")?,
+            g_force_z: find("sim/flightmodel2/misc/gforce_normal")?,
+            on_ground: find("sim/flightmodel/failures/onground_any")?,
+            groundspeed: find("sim/flightmodel/position/groundspeed")?,
+            indicated_airspeed: find("sim/flightmodel/position/indicated_airspeed")?,
+            angle_of_attack: find("sim/flightmodel2/misc/AoA_angle_degrees")?,
+            gear_deploy: find("sim/flightmodel2/gear/deploy_ratio")?,
+            flap_deploy: find("sim/flightmodel2/controls/flap1_deploy_ratio")?,
+            paused: find("sim/time/paused")?,
+            replay: find("sim/operation/prefs/replay_mode")?,
+            view_type: find("sim/graphics/view/view_type")?,
+        })
+    }
+
+    /// Read the current state. `dt` is the time since the previous sample.
+    pub fn sample(&self, dt: f32) -> SimSample {
+        let mut gear = [0.0; GEAR_LEGS];
+        let legs = self.gear_deploy.get(&mut gear).min(GEAR_LEGS);
+        SimSample {
+            sim_time: self.sim_time.get() as f64,
+            dt,
+            g_force: (
+                self.g_force_x.get(),
+                self.g_force_y.get(),
+                self.g_force_z.get(),
+            ),
+            on_ground: self.on_ground.get() != 0,
+            groundspeed: self.groundspeed.get(),
+            indicated_airspeed: self.indicated_airspeed.get(),
+            angle_of_attack: self.angle_of_attack.get(),
+            gear_deploy: gear[..legs].iter().copied().fold(0.0, f32::max),
+            flap_deploy: self.flap_deploy.get(),
+            paused: self.paused.get() != 0,
+            replay: self.replay.get() != 0,
+            in_menu: self.view_type.get() < FIRST_SIM_VIEW_TYPE,
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use crate::backlight::Backlight;
+use crate::control::{SharedControl, MAX_GAIN, MIN_GAIN};
+use crate::flight_loop::FlightLoopInterval;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use xa_ursa_minor_hid::engine::{Tunable, WaveMerge};
+use xa_ursa_minor_hid::writer::HidWriter;
+use xplm::flight_loop::{FlightLoop, LoopState};
+use xplm_sys::{
+    xpMessage_CloseButtonPushed, xpMsg_PushButtonPressed, xpMsg_ScrollBarSliderPositionChanged,
+    xpProperty_MainWindowHasCloseBoxes, xpProperty_ProgressMax, xpProperty_ProgressMin,
+    xpProperty_ProgressPosition, xpProperty_Refcon, xpProperty_ScrollBarMax,
+    xpProperty_ScrollBarMin, xpProperty_ScrollBarSliderPosition, xpProperty_ScrollBarType,
+    xpScrollBarTypeSlider, xpWidgetClass_Button, xpWidgetClass_Caption, xpWidgetClass_MainWindow,
+    xpWidgetClass_Progress, xpWidgetClass_ScrollBar, XPAddWidgetCallback, XPBringRootWidgetToFront,
+    XPCreateWidget, XPDestroyWidget, XPGetWidgetProperty, XPHideWidget, XPIsWidgetVisible,
+    XPSetWidgetDescriptor, XPSetWidgetProperty, XPShowWidget, XPWidgetID, XPWidgetMessage,
+};
+
+/// Where the window opens, in global screen coordinates.
+const WINDOW_LEFT: c_int = 100;
+const WINDOW_TOP: c_int = 700;
+const WINDOW_WIDTH: c_int = 440;
+/// Height of the title bar and of each row of controls.
+const TITLE_HEIGHT: c_int = 24;
+const ROW_HEIGHT: c_int = 24;
+const LABEL_WIDTH: c_int = 130;
+const VALUE_WIDTH: c_int = 60;
+/// How often the intensity meter is redrawn while the window is open.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the device status line is updated.
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A value edited with a slider.
+#[derive(Clone, Copy)]
+enum Setting {
+    Gain,
+    Backlight,
+    /// Flight loop interval in seconds; 0 runs it every frame.
+    SampleInterval,
+    Tunable(Tunable),
+}
+
+impl Setting {
+    fn label(self) -> &'static str {
+        match self {
+            Setting::Gain => "Intensity gain",
+            Setting::Backlight => "Backlight",
+            Setting::SampleInterval => "Sample interval (s)",
+            Setting::Tunable(tunable) => tunable.label(),
+        }
+    }
+
+    /// `(min, max, step)`; the slider moves in whole steps.
+    fn range(self) -> (f32, f32, f32) {
+        match self {
+            Setting::Gain => (MIN_GAIN, MAX_GAIN, 0.05),
+            Setting::Backlight => (0.0, 255.0, 1.0),
+            Setting::SampleInterval => (0.0, 0.1, 0.005),
+            Setting::Tunable(tunable) => tunable.range(),
+        }
+    }
+
+    fn position_of(self, value: f32) -> isize {
+        let (min, _, step) = self.range();
+        ((value - min) / step).round() as isize
+    }
+
+    fn value_at(self, position: isize) -> f32 {
+        let (min, max, step) = self.range();
+        (min + position as f32 * step).clamp(min, max)
+    }
+}
+
+struct Slider {
+    setting: Setting,
+    scrollbar: XPWidgetID,
+    value: XPWidgetID,
+}
+
+struct Widgets {
+    window: XPWidgetID,
+    device: XPWidgetID,
+    meter: XPWidgetID,
+    profile: XPWidgetID,
+    previous_profile: XPWidgetID,
+    next_profile: XPWidgetID,
+    sliders: Vec<Slider>,
+}
+
+struct SettingsState {
+    control: SharedControl,
+    backlight: Backlight,
+    writer: Arc<HidWriter>,
+    current_intensity: Arc<AtomicU8>,
+    /// Created the first time the window is shown.
+    widgets: Option<Widgets>,
+    last_device_check: Option<Instant>,
+}
+
+impl SettingsState {
+    fn value(&self, setting: Setting) -> f32 {
+        match setting {
+            Setting::Gain => self.control.borrow().gain(),
+            Setting::Backlight => self.backlight.level() as f32,
+            Setting::SampleInterval => match self.control.borrow().sample_interval() {
+                FlightLoopInterval::Frames(_) => 0.0,
+                FlightLoopInterval::Seconds(seconds) => seconds,
+            },
+            Setting::Tunable(tunable) => tunable.get(self.control.borrow().params()),
+        }
+    }
+
+    /// Changes go straight to the running worker (or the device, for the backlight).
+    fn apply(&self, setting: Setting, value: f32) {
+        match setting {
+            Setting::Gain => self.control.borrow_mut().set_gain(value),
+            Setting::Backlight => self.backlight.set(value.round() as u8),
+            Setting::SampleInterval => {
+                let interval = if value > 0.0 {
+                    FlightLoopInterval::Seconds(value)
+                } else {
+                    FlightLoopInterval::Frames(1)
+                };
+                self.control.borrow_mut().set_sample_interval(interval);
+            }
+            Setting::Tunable(tunable) => self.control.borrow_mut().set_tunable(tunable, value),
+        }
+    }
+
+    unsafe fn create_widgets(&self, refcon: isize) -> Widgets {
+        let mut settings = vec![Setting::Gain, Setting::Backlight, Setting::SampleInterval];
+        settings.extend(
+            Tunable::ALL
+                .iter()
+                .map(|&tunable| Setting::Tunable(tunable)),
+        );
+
+        let rows = 3 + settings.len() as c_int;
+        let right = WINDOW_LEFT + WINDOW_WIDTH;
+        let bottom = WINDOW_TOP - TITLE_HEIGHT - rows * ROW_HEIGHT - 10;
+        let window = create_widget(
+            (WINDOW_LEFT, WINDOW_TOP, right, bottom),
+            "XA URSA Minor",
+            ptr::null_mut(),
+            xpWidgetClass_MainWindow as _,
+        );
+        XPSetWidgetProperty(window, xpProperty_MainWindowHasCloseBoxes as _, 1);
+        XPSetWidgetProperty(window, xpProperty_Refcon as _, refcon);
+        XPAddWidgetCallback(window, Some(handle_message));
+
+        // Row `n` spans from `row_top(n)` down one row height, inset from the window edges.
+        let left = WINDOW_LEFT + 10;
+        let right = right - 10;
+        let row_top = |row: c_int| WINDOW_TOP - TITLE_HEIGHT - row * ROW_HEIGHT;
+        let row_bottom = |row: c_int| row_top(row) - ROW_HEIGHT + 4;
+
+        let device = create_widget(
+            (left, row_top(0), right, row_bottom(0)),
+            "Device: checking...",
+            window,
+            xpWidgetClass_Caption as _,
+        );
+
+        create_widget(
+            (left, row_top(1), left + LABEL_WIDTH, row_bottom(1)),
+            "Intensity",
+            window,
+            xpWidgetClass_Caption as _,
+        );
+        let meter = create_widget(
+            (left + LABEL_WIDTH, row_top(1), right, row_bottom(1)),
+            "",
+            window,
+            xpWidgetClass_Progress as _,
+        );
+        XPSetWidgetProperty(meter, xpProperty_ProgressMin as _, 0);
+        XPSetWidgetProperty(meter, xpProperty_ProgressMax as _, 255);
+
+        create_widget(
+            (left, row_top(2), left + LABEL_WIDTH, row_bottom(2)),
+            "Profile",
+            window,
+            xpWidgetClass_Caption as _,
+        );
+        let previous_profile = create_widget(
+            (
+                left + LABEL_WIDTH,
+                row_top(2),
+                left + LABEL_WIDTH + 30,
+                row_bottom(2),
+            ),
+            "<",
+            window,
+            xpWidgetClass_Button as _,
+        );
+        let profile = create_widget(
+            (
+                left + LABEL_WIDTH + 40,
+                row_top(2),
+                right - 40,
+                row_bottom(2),
+            ),
+            "",
+            window,
+            xpWidgetClass_Caption as _,
+        );
+        let next_profile = create_widget(
+            (right - 30, row_top(2), right, row_bottom(2)),
+            ">",
+            window,
+            xpWidgetClass_Button as _,
+        );
+
+        let sliders = settings
+            .into_iter()
+            .enumerate()
+            .map(|(index, setting)| {
+                let row = 3 + index as c_int;
+                create_widget(
+                    (left, row_top(row), left + LABEL_WIDTH, row_bottom(row)),
+                    setting.label(),
+                    window,
+                    xpWidgetClass_Caption as _,
+                );
+                let scrollbar = create_widget(
+                    (
+                        left + LABEL_WIDTH,
+                        row_top(row),
+                        right - VALUE_WIDTH - 10,
+                        row_bottom(row),
+                    ),
+                    "",
+                    window,
+                    xpWidgetClass_ScrollBar as _,
+                );
+                let (_, max, _) = setting.range();
+                XPSetWidgetProperty(
+                    scrollbar,
+                    xpProperty_ScrollBarType as _,
+                    xpScrollBarTypeSlider as _,
+                );
+                XPSetWidgetProperty(scrollbar, xpProperty_ScrollBarMin as _, 0);
+                XPSetWidgetProperty(
+                    scrollbar,
+                    xpProperty_ScrollBarMax as _,
+                    setting.position_of(max),
+                );
+                let value = create_widget(
+                    (right - VALUE_WIDTH, row_top(row), right, row_bottom(row)),
+                    "",
+                    window,
+                    xpWidgetClass_Caption as _,
+                );
+                Slider {
+                    setting,
+                    scrollbar,
+                    value,
+                }
+            })
+            .collect();
+
+        Widgets {
+            window,
+            device,
+            meter,
+            profile,
+            previous_profile,
+            next_profile,
+            sliders,
+        }
+    }
+
+    /// Bring every slider and the profile name in line with the current values.
+    fn sync(&self) {
+        let Some(widgets) = &self.widgets else {
+            return;
+        };
+        for slider in &widgets.sliders {
+            let value = self.value(slider.setting);
+            unsafe {
+                XPSetWidgetProperty(
+                    slider.scrollbar,
+                    xpProperty_ScrollBarSliderPosition as _,
+                    slider.setting.position_of(value),
+                );
+            }
+            set_text(slider.value, &format_value(slider.setting, value));
+        }
+        set_text(widgets.profile, &self.control.borrow().profile().name);
+    }
+
+    /// Handle a message sent to the window. Returns whether it was consumed.
+    fn handle_message(&mut self, message: XPWidgetMessage, param1: isize) -> bool {
+        let Some(widgets) = &self.widgets else {
+            return false;
+        };
+        let source = param1 as XPWidgetID;
+        if message == xpMessage_CloseButtonPushed as XPWidgetMessage {
+            unsafe { XPHideWidget(widgets.window) };
+            true
+        } else if message == xpMsg_PushButtonPressed as XPWidgetMessage {
+            let step = if source == widgets.previous_profile {
+                -1
+            } else if source == widgets.next_profile {
+                1
+            } else {
+                return false;
+            };
+            let mut control = self.control.borrow_mut();
+            let count = control.profiles().len() as isize;
+            let index = (control.profile_index() as isize + step).rem_euclid(count);
+            control.select_profile(index as usize);
+            set_text(widgets.profile, &control.profile().name);
+            true
+        } else if message == xpMsg_ScrollBarSliderPositionChanged as XPWidgetMessage {
+            let Some(slider) = widgets
+                .sliders
+                .iter()
+                .find(|slider| slider.scrollbar == source)
+            else {
+                return false;
+            };
+            let position = unsafe {
+                XPGetWidgetProperty(
+                    slider.scrollbar,
+                    xpProperty_ScrollBarSliderPosition as _,
+                    ptr::null_mut(),
+                )
+            };
+            let value = slider.setting.value_at(position);
+            self.apply(slider.setting, value);
+            set_text(slider.value, &format_value(slider.setting, value));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Update the live parts of the window. Returns false once the window is closed.
+    fn refresh(&mut self) -> bool {
+        let Some(widgets) = &self.widgets else {
+            return false;
+        };
+        if unsafe { XPIsWidgetVisible(widgets.window) } == 0 {
+            return false;
+        }
+
+        let intensity = self.current_intensity.load(Ordering::Relaxed);
+        unsafe {
+            XPSetWidgetProperty(
+                widgets.meter,
+                xpProperty_ProgressPosition as _,
+                intensity as isize,
+            );
+        }
+
+        let now = Instant::now();
+        let check_due = self
+            .last_device_check
+            .is_none_or(|last| now.duration_since(last) >= DEVICE_CHECK_INTERVAL);
+        if check_due {
+            self.last_device_check = Some(now);
+            // Cached by the writer thread, so this never touches the device.
+            let serial = self.writer.serial_number();
+            let stats = self.writer.stats();
+            let status = match serial {
+                Some(_) if self.writer.stalled() => "Device: not responding".to_string(),
+                Some(serial) => format!(
+                    "Device: connected (S/N {serial}), writes {:.1} ms avg, {:.1} ms max",
+                    stats.mean_latency().as_secs_f32() * 1000.0,
+                    stats.max_latency.as_secs_f32() * 1000.0
+                ),
+                None => "Device: not connected".to_string(),
+            };
+            set_text(widgets.device, &status);
+        }
+        true
+    }
+
+    fn destroy(&mut self) {
+        if let Some(widgets) = self.widgets.take() {
+            unsafe { XPDestroyWidget(widgets.window, 1) };
+        }
+    }
+}
+
+/// In-sim settings window: device status, a live intensity meter, profile selection and sliders
+/// for the vibration parameters and the backlight. Every change applies to the running worker
+/// straight away.
+pub struct SettingsWindow {
+    state: Rc<RefCell<SettingsState>>,
+    /// Refreshes the meter and device status while the window is open.
+    refresh_loop: FlightLoop,
+}
+
+impl SettingsWindow {
+    pub fn new(
+        control: SharedControl,
+        backlight: Backlight,
+        writer: Arc<HidWriter>,
+        current_intensity: Arc<AtomicU8>,
+    ) -> Self {
+        let state = Rc::new(RefCell::new(SettingsState {
+            control,
+            backlight,
+            writer,
+            current_intensity,
+            widgets: None,
+            last_device_check: None,
+        }));
+        let refresh_state = Rc::clone(&state);
+        let refresh_loop = FlightLoop::new(move |loop_state: &mut LoopState| {
+            if refresh_state.borrow_mut().refresh() {
+                loop_state.call_next_after(REFRESH_INTERVAL);
+            } else {
+                loop_state.deactivate();
+            }
+        });
+        Self {
+            state,
+            refresh_loop,
+        }
+    }
+
+    /// Open the window, or bring it to the front if it is already open.
+    pub fn show(&mut self) {
+        {
+            let mut state = self.state.borrow_mut();
+            if state.widgets.is_none() {
+                // The widget callback finds the state through the window's refcon. The state
+                // lives in the `Rc` for as long as the widgets do.
+                let refcon = Rc::as_ptr(&self.state) as isize;
+                state.widgets = Some(unsafe { state.create_widgets(refcon) });
+            }
+            state.last_device_check = None;
+            state.sync();
+            if let Some(widgets) = &state.widgets {
+                unsafe {
+                    XPShowWidget(widgets.window);
+                    XPBringRootWidgetToFront(widgets.window);
+                }
+            }
+        }
+        self.refresh_loop.schedule_immediate();
+    }
+}
+
+impl Drop for SettingsWindow {
+    fn drop(&mut self) {
+        self.refresh_loop.deactivate();
+        self.state.borrow_mut().destroy();
+    }
+}
+
+unsafe extern "C" fn handle_message(
+    message: XPWidgetMessage,
+    widget: XPWidgetID,
+    param1: isize,
+    _param2: isize,
+) -> c_int {
+    let state = XPGetWidgetProperty(widget, xpProperty_Refcon as _, ptr::null_mut())
+        as *const RefCell<SettingsState>;
+    if state.is_null() {
+        return 0;
+    }
+    // Widget calls made while handling a message can send us another one; skip those.
+    let Ok(mut state) = (*state).try_borrow_mut() else {
+        return 0;
+    };
+    state.handle_message(message, param1) as c_int
+}
+
+unsafe fn create_widget(
+    (left, top, right, bottom): (c_int, c_int, c_int, c_int),
+    text: &str,
+    container: XPWidgetID,
+    class: c_int,
+) -> XPWidgetID {
+    let text = CString::new(text).unwrap_or_default();
+    let is_root = container.is_null() as c_int;
+    XPCreateWidget(
+        left,
+        top,
+        right,
+        bottom,
+        1,
+        text.as_ptr(),
+        is_root,
+        container,
+        class as _,
+    )
+}
+
+fn set_text(widget: XPWidgetID, text: &str) {
+    let text = CString::new(text).unwrap_or_default();
+    unsafe { XPSetWidgetDescriptor(widget, text.as_ptr()) };
+}
+
+fn format_value(setting: Setting, value: f32) -> String {
+    match setting {
+        Setting::Gain => format!("{:.0}%", value * 100.0),
+        Setting::Backlight
+        | Setting::Tunable(Tunable::MinMotorIntensity)
+        | Setting::Tunable(Tunable::MaxWaves) => format!("{value:.0}"),
+        Setting::Tunable(Tunable::WaveMerge) => WaveMerge::ALL
+            .get(value.round() as usize)
+            .map_or_else(String::new, |merge| merge.name().to_string()),
+        Setting::Tunable(Tunable::MergeWindow) => format!("{value:.3}"),
+        Setting::SampleInterval if value == 0.0 => "frame".to_string(),
+        Setting::SampleInterval => format!("{value:.3}"),
+        Setting::Tunable(_) => format!("{value:.2}"),
+    }
+}
@@ -0,0 +1,65 @@
+use crate::plugin_debugln;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use xa_ursa_minor_hid::telemetry::{TelemetryRecord, TelemetryWriter};
+
+/// Largest size of one recording, across its CSV and binary files. The recording stops there.
+pub const MAX_RECORDING_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A telemetry recording in progress on the vibration worker.
+pub struct Recording {
+    writer: TelemetryWriter<BufWriter<File>>,
+    started: Instant,
+}
+
+impl Recording {
+    /// Start a recording in `dir`, named after the current time.
+    pub fn start(dir: &Path) -> Result<Self, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or_default();
+        let (writer, path) =
+            TelemetryWriter::create(dir, &format!("telemetry-{timestamp}"), MAX_RECORDING_BYTES)?;
+        plugin_debugln!("Recording telemetry to {}", path.display());
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    /// Seconds since the recording started.
+    pub fn time(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// Append a record. Returns false once the recording can't go on (size cap reached or write
+    /// error); it should then be finished.
+    pub fn write(&mut self, record: &TelemetryRecord) -> bool {
+        match self.writer.write(record) {
+            Ok(true) => true,
+            Ok(false) => {
+                plugin_debugln!(
+                    "Telemetry recording reached {} bytes, stopping",
+                    MAX_RECORDING_BYTES
+                );
+                false
+            }
+            Err(e) => {
+                plugin_debugln!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Flush the files and end the recording.
+    pub fn finish(self) {
+        let written = self.writer.written();
+        match self.writer.finish() {
+            Ok(()) => plugin_debugln!("Telemetry recording finished ({} bytes)", written),
+            Err(e) => plugin_debugln!("{}", e),
+        }
+    }
+}
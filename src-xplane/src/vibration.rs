@@ -1,241 +1,377 @@
-use std::sync::mpsc::Receiver;
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 use crate::plugin_debugln;
-use xa_ursa_minor_hid::hid::HIDWrapper;
-/// How often the worker processes new buffer items (e.g. ~50 Hz).
-pub static mut PROCESS_INTERVAL: Duration = Duration::from_millis(20);
-/// Duration of each new wave in seconds.
-pub static mut WAVE_DURATION: f32 = 0.2;
-/// Map magnitude to [0..255]. Adjust `MAX_MAG` to fit your typical input range.
-pub static mut MAX_MAG: f32 = 1.5;
-/// Only bother writing intensities above this threshold (like your existing 5).
-pub static mut MIN_MOTOR_INTENSITY: u8 = 3;
-pub static mut HIGH_PASS_ALPHA: f32 = 0.9;
-pub static mut BASE_FREQ: f32 = 1.0; // Starting frequency
-pub static mut FREQ_SENSITIVITY: f32 = 2.0; // Scale factor for delta -> frequency
-pub static mut BASE_SHARPNESS: f32 = 1.0; // Starting sharpness
-pub static mut SHARPNESS_SENSITIVITY: f32 = 2.0; // Scale factor for delta -> sharpness (raising sine wave)
-/// Simple 3D high-pass filter using the one-pole method.
-struct HighPassFilter3D {
-    alpha: f32,
-    prev_input: (f32, f32, f32),
-    prev_output: (f32, f32, f32),
+use crate::telemetry::Recording;
+use xa_ursa_minor_hid::engine::{EngineParams, Tunable, VibrationEngine};
+use xa_ursa_minor_hid::pattern::Pattern;
+use xa_ursa_minor_hid::profile::Profile;
+use xa_ursa_minor_hid::ring::{RingReceiver, RingWaker};
+use xa_ursa_minor_hid::sample::SimSample;
+use xa_ursa_minor_hid::telemetry::{SampleRecord, TelemetryRecord};
+use xa_ursa_minor_hid::writer::HidWriter;
+/// How often the engine is updated and the motor written while effects are running (~50 Hz).
+pub const PROCESS_INTERVAL: Duration = Duration::from_millis(20);
+/// Log every layer's intensity whenever the motor output changes. Debugging aid only.
+pub static LOG_LAYERS: AtomicBool = AtomicBool::new(false);
+
+/// Samples the flight loop can queue for the worker, about a second at 60 fps. If the worker
+/// falls further behind, the oldest samples are dropped.
+pub const SAMPLE_CAPACITY: usize = 64;
+
+/// Samples that waited longer than this, e.g. while the worker was stalled, are discarded rather
+/// than turned into a burst of late waves.
+pub const MAX_SAMPLE_AGE: Duration = Duration::from_millis(100);
+
+/// How long the worker waits on exit for the motor to be zeroed.
+const STOP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Commands sent from the main thread (flight loop, datarefs, X-Plane commands) to the vibration
+/// worker, alongside the g-force samples.
+pub enum VibrationCommand {
+    /// Start (`true`) or stop (`false`) the stick shaker.
+    StickShaker(bool),
+    /// Play a haptic pattern, replacing the one currently playing.
+    PlayPattern(Pattern),
+    /// Stop the current pattern.
+    StopPattern,
+    /// Hold the external layer at the given intensity, or release it with `None`.
+    SetOverride(Option<u8>),
+    /// Mute (`false`) or unmute (`true`) the motor. Effects keep running while muted.
+    SetEnabled(bool),
+    /// Scale the composed motor output (1.0 = unchanged).
+    SetGain(f32),
+    /// Change an engine parameter.
+    SetTunable(Tunable, f32),
+    /// Switch to another profile. Waves already running keep their shape.
+    SetProfile(Profile),
+    /// The HID device was reopened, e.g. after the stick was unplugged; start the motor from a
+    /// known state.
+    Reconnect,
+    /// Record telemetry into files in the given directory.
+    StartRecording(PathBuf),
+    /// Finish the telemetry recording.
+    StopRecording,
 }
 
-impl HighPassFilter3D {
-    /// Create a new high-pass filter with a given alpha (0..1).
-    /// Larger alpha => stronger high-pass (keeps more high-frequency).
-    /// Smaller alpha => more smoothing, less “buzz.”
-    pub fn new(alpha: f32) -> Self {
-        Self {
-            alpha,
-            // Start with zeros; or you could initialize differently if desired
-            prev_input: (0.0, 0.0, 0.0),
-            prev_output: (0.0, 0.0, 0.0),
-        }
-    }
-
-    /// Filter the given (x, y, z) and return the high-passed (x, y, z).
-    pub fn filter(&mut self, current_input: (f32, f32, f32)) -> (f32, f32, f32) {
-        let (cx, cy, cz) = current_input;
-        let (px, py, pz) = self.prev_input;
-        let (ox, oy, oz) = self.prev_output;
-
-        // Apply high-pass for each axis
-        let out_x = self.alpha * (ox + cx - px);
-        let out_y = self.alpha * (oy + cy - py);
-        let out_z = self.alpha * (oz + cz - pz);
-
-        // Update state
-        self.prev_input = current_input;
-        self.prev_output = (out_x, out_y, out_z);
-
-        (out_x, out_y, out_z)
-    }
-}
-
-/// A single “wave event” that starts at `start_time`, has a peak amplitude
-/// (`target_intensity`), and lasts for `WAVE_DURATION`.
-struct WaveEvent {
-    start_time: Instant,
-    target_intensity: u8,
-    wave_frequency: f32, // per-wave frequency
-    wave_sharpness: f32, // per-wave shaping exponent
+/// Sends commands to the worker and wakes it, so they apply right away even while it's idle.
+#[derive(Clone)]
+pub struct CommandSender {
+    commands: Sender<VibrationCommand>,
+    waker: RingWaker<SimSample>,
 }
 
-impl WaveEvent {
-    /// Return the intensity of this wave at the given `now` instant.
-    /// If the wave has expired, return `None`.
-    unsafe fn current_intensity(&self, now: Instant) -> Option<u8> {
-        let elapsed = now.duration_since(self.start_time).as_secs_f32();
-        if elapsed > WAVE_DURATION {
-            // Wave is fully expired
-            return None;
-        }
-
-        // 0..1 progress through the wave
-        let progress = elapsed / WAVE_DURATION;
-
-        // Example: full sine wave from 0..(2π * wave_frequency)
-        let raw_sine = (progress * std::f32::consts::TAU * self.wave_frequency).sin();
-
-        // We only want positive arcs. If you prefer a half-sine from 0..π, do:
-        //   let raw_sine = (progress * std::f32::consts::PI).sin();
-        // (It starts at 0, up to 1, back to 0, without going negative.)
-
-        let shaped = if raw_sine < 0.0 {
-            0.0
-        } else {
-            // Raise sine to wave_sharpness for steeper rise/fall
-            raw_sine.powf(self.wave_sharpness)
-        };
-
-        let intensity_f = shaped * (self.target_intensity as f32);
-        let intensity = intensity_f.round().clamp(0.0, 255.0) as u8;
+impl CommandSender {
+    pub fn new(commands: Sender<VibrationCommand>, waker: RingWaker<SimSample>) -> Self {
+        Self { commands, waker }
+    }
 
-        Some(intensity)
+    pub fn send(&self, command: VibrationCommand) -> Result<(), String> {
+        self.commands
+            .send(command)
+            .map_err(|_| "The vibration worker has stopped".to_string())?;
+        self.waker.wake();
+        Ok(())
     }
 }
 
-/// VibrationManager now spawns wave events and merges them by taking a pointwise max.
+/// Runs the `VibrationEngine` on the worker thread in real time, and writes its output to the
+/// stick.
 pub struct VibrationManager {
-    /// The list of active waves. We add a new wave whenever we get new input.
-    /// We remove waves once they’re expired.
-    waves: Vec<WaveEvent>,
+    engine: VibrationEngine,
 
-    /// HID device wrapper.
-    hid_wrapper: HIDWrapper,
+    /// Writes to the device without blocking the worker.
+    writer: Arc<HidWriter>,
 
     /// Track the last written intensity so we can avoid spamming the same value.
     last_intensity: u8,
 
-    hp_filter: HighPassFilter3D,
+    /// Last written intensity, shared with the flight loop (e.g. for the `current_intensity`
+    /// dataref).
+    current_intensity: Arc<AtomicU8>,
 
-    previous_mag: f32,
+    /// Engine time 0.
+    started: Instant,
+
+    /// Telemetry recording in progress, if any.
+    recording: Option<Recording>,
 }
 
 impl VibrationManager {
-    /// Create a new manager with no active waves.
-    pub unsafe fn new(hid_wrapper: HIDWrapper) -> Self {
+    pub fn new(
+        writer: Arc<HidWriter>,
+        profile: Profile,
+        params: EngineParams,
+        current_intensity: Arc<AtomicU8>,
+    ) -> Self {
         Self {
-            waves: Vec::new(),
-            hid_wrapper,
+            engine: VibrationEngine::new(profile, params),
+            writer,
             last_intensity: 0,
-            hp_filter: HighPassFilter3D::new(HIGH_PASS_ALPHA),
-            previous_mag: 0.0,
+            current_intensity,
+            started: Instant::now(),
+            recording: None,
         }
     }
 
-    /// Convert (ax, ay, az) -> magnitude -> wave with a certain peak intensity, then store it.
-    pub unsafe fn spawn_wave_for_input(&mut self, ax: f32, ay: f32, az: f32) {
-        let (fx, fy, fz) = self.hp_filter.filter((ax, ay, az));
-
-        // Example magnitude is sqrt(fx^2 + fy^2 + fz^2).
-        let mag = (fx.powi(2) + fy.powi(2) + fz.powi(2)).sqrt();
-
-        // Convert magnitude to [0..255]
-        let scaled = (mag / MAX_MAG) * 255.0;
-        let target_intensity = scaled.clamp(0.0, 255.0) as u8;
+    /// Current engine time, in seconds.
+    fn now(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
 
-        // If the scaled intensity is trivial (like 0), skip spawning wave
-        if target_intensity == 0 {
-            return;
+    /// Apply a command received from the main thread.
+    pub fn handle_command(&mut self, command: VibrationCommand) {
+        let now = self.now();
+        match command {
+            VibrationCommand::StickShaker(active) => {
+                plugin_debugln!("Stick shaker -> {}", if active { "on" } else { "off" });
+                self.engine.set_stick_shaker(active, now);
+            }
+            VibrationCommand::PlayPattern(pattern) => {
+                plugin_debugln!("Playing pattern \"{}\"", pattern.name);
+                self.engine.play_pattern(pattern, now);
+            }
+            VibrationCommand::StopPattern => self.engine.stop_pattern(),
+            VibrationCommand::SetOverride(intensity) => self.engine.set_override(intensity),
+            VibrationCommand::SetEnabled(enabled) => {
+                plugin_debugln!("Vibration {}", if enabled { "enabled" } else { "disabled" });
+                self.engine.set_enabled(enabled);
+            }
+            VibrationCommand::SetGain(gain) => self.engine.set_gain(gain),
+            VibrationCommand::SetTunable(tunable, value) => self.engine.set_tunable(tunable, value),
+            VibrationCommand::SetProfile(profile) => {
+                plugin_debugln!("Switched to vibration profile \"{}\"", profile.name);
+                self.engine.set_profile(profile);
+            }
+            VibrationCommand::Reconnect => {
+                self.last_intensity = 0;
+                self.writer.set_motor(0);
+            }
+            VibrationCommand::StartRecording(dir) => self.start_recording(&dir),
+            VibrationCommand::StopRecording => self.stop_recording(),
         }
+    }
 
-        // ------------------- NEW: compute delta and map it to frequency/sharpness -------------------
-        let delta_mag = (mag - self.previous_mag).abs();
-        self.previous_mag = mag; // update for next call
+    /// Feed a sample from the flight loop to the engine, which may spawn a wave from it.
+    pub fn push_sample(&mut self, sample: &SimSample) {
+        let was_suppressed = self.engine.suppressed();
+        let processed = self.engine.push_sample(sample, self.now());
+        match (was_suppressed, self.engine.suppressed()) {
+            (false, true) => plugin_debugln!("Sim paused or in replay, suppressing vibration"),
+            (true, false) => plugin_debugln!("Sim resumed, vibration back on"),
+            _ => {}
+        }
+        self.record(|time| {
+            TelemetryRecord::Sample(SampleRecord {
+                time,
+                sample: *sample,
+                delta: processed.delta,
+                filtered: processed.filtered,
+            })
+        });
+    }
 
-        // Dynamic frequency: e.g., base + some sensitivity * delta
-        let wave_frequency = BASE_FREQ + FREQ_SENSITIVITY * delta_mag;
+    /// Start recording telemetry into `dir`, replacing any recording in progress.
+    fn start_recording(&mut self, dir: &Path) {
+        self.stop_recording();
+        match Recording::start(dir) {
+            Ok(recording) => self.recording = Some(recording),
+            Err(e) => plugin_debugln!("Failed to start telemetry recording: {}", e),
+        }
+    }
 
-        // Dynamic sharpness: e.g., base + some sensitivity * delta
-        // clamp or limit if you like (to avoid going too high)
-        let wave_sharpness = (BASE_SHARPNESS + SHARPNESS_SENSITIVITY * delta_mag).clamp(1.0, 5.0);
+    /// Finish the recording in progress, if any.
+    pub fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            recording.finish();
+        }
+    }
 
-        // Create a new wave event
-        let wave = WaveEvent {
-            start_time: Instant::now(),
-            target_intensity,
-            wave_frequency,
-            wave_sharpness,
+    /// Add a record to the recording in progress, if any. `record` gets the recording time.
+    fn record(&mut self, record: impl FnOnce(f64) -> TelemetryRecord) {
+        let Some(recording) = &mut self.recording else {
+            return;
         };
+        if !recording.write(&record(recording.time())) {
+            self.stop_recording();
+        }
+    }
 
-        // Insert wave into the list
-        self.waves.push(wave);
+    /// Write zero to the motor, whatever the layers are doing. Used when the worker exits.
+    pub fn stop_motor(&mut self) {
+        self.writer.set_motor(0);
+        if !self.writer.flush(STOP_TIMEOUT) {
+            plugin_debugln!("Timed out stopping vibration");
+        }
+        self.last_intensity = 0;
+        self.current_intensity.store(0, Ordering::Relaxed);
     }
 
-    /// Called regularly (e.g. every 20ms) to update waves and send motor commands.
-    pub unsafe fn update(&mut self) {
-        let now = Instant::now();
+    /// Whether `update` has to keep being called for the effects in progress.
+    pub fn needs_updates(&self) -> bool {
+        self.engine.needs_updates()
+    }
 
-        // Compute the maximum intensity across all active waves
-        let mut max_intensity = 0u8;
-        self.waves.retain(|wave| unsafe {
-            if let Some(current) = wave.current_intensity(now) {
-                if current > max_intensity {
-                    max_intensity = current;
-                }
-                true // wave is still active
-            } else {
-                false // wave has expired
-            }
-        });
+    /// Called every `PROCESS_INTERVAL` while effects run, and after every change, to update
+    /// waves and send motor commands.
+    pub fn update(&mut self) {
+        let output = self.engine.update(self.now());
 
-        // Write to motor only if:
-        //  - the max intensity is above threshold, or
-        //  - it’s zero but the last intensity was non-zero
-        if max_intensity >= MIN_MOTOR_INTENSITY {
-            if max_intensity != self.last_intensity {
-                // plugin_debugln!("Vibration Intensity -> {}", max_intensity);
-                if let Err(e) = self.hid_wrapper.write_vibration(max_intensity) {
-                    plugin_debugln!("Failed to write vibration to device: {}", e);
-                }
-                self.last_intensity = max_intensity;
-            }
-        } else {
-            // If the new max is 0 but we previously had something non-zero, then set to 0
-            if max_intensity == 0 && self.last_intensity != 0 {
+        // Write to motor only if the value changed, so we don't spam the device.
+        if output != self.last_intensity {
+            if output == 0 {
                 plugin_debugln!("Vibration Intensity -> 0");
-                if let Err(e) = self.hid_wrapper.write_vibration(0) {
-                    plugin_debugln!("Failed to write vibration to device: {}", e);
+            }
+            if LOG_LAYERS.load(Ordering::Relaxed) {
+                for status in self.engine.layer_status() {
+                    plugin_debugln!(
+                        "Layer {} (priority {}, {:?}, gain {:.2}): {} from {} waves",
+                        status.name,
+                        status.priority,
+                        status.blend,
+                        status.gain,
+                        status.intensity,
+                        status.active_waves
+                    );
                 }
-                self.last_intensity = 0;
+            }
+            self.writer.set_motor(output);
+            self.last_intensity = output;
+            self.current_intensity.store(output, Ordering::Relaxed);
+            self.record(|time| TelemetryRecord::Output {
+                time,
+                intensity: output,
+            });
+        }
+    }
+}
+
+/// Handle to a running vibration worker. Stopping or dropping it ends the thread, which zeroes
+/// the motor on its way out.
+pub struct VibrationWorker {
+    stop: Arc<AtomicBool>,
+    /// Wakes the worker so it sees the stop flag, even while it's idle.
+    waker: RingWaker<SimSample>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VibrationWorker {
+    /// Ask the worker to finish and wait for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.waker.wake();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                plugin_debugln!("Vibration worker panicked");
             }
         }
     }
 }
 
+impl Drop for VibrationWorker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
 /// Worker thread:
-///   1. Receives (x, y, z) from flight loop or other source.
+///   1. Receives sim samples from the flight loop or other source.
 ///   2. Spawns a wave on each new input.
-///   3. Updates/merges waves every `PROCESS_INTERVAL`.
-pub fn start_vibration_thread(rx: Receiver<(f32, f32, f32)>) {
-    thread::spawn(move || unsafe {
-        // Try to open the HID device once. If that fails, bail out.
-        let hid_wrapper = match HIDWrapper::new() {
-            Some(h) => h,
-            None => {
-                plugin_debugln!("Could not open HID device! Vibration will be disabled.");
-                return;
-            }
-        };
+///   3. Applies commands (e.g. stick shaker on/off, patterns).
+///   4. Updates/merges waves every `PROCESS_INTERVAL` while any are running.
+///
+/// The worker sleeps until a sample or command arrives or the next update is due. A change while
+/// the engine is idle is written right away; while effects run, the output keeps a fixed rate.
+/// With nothing running and nothing arriving, it sleeps indefinitely.
+///
+/// Commands have to go through a `CommandSender` waking this worker's sample receiver.
+///
+/// Runs until the returned handle is stopped or either sender is dropped, then zeroes the motor.
+pub fn start_vibration_thread(
+    rx: RingReceiver<SimSample>,
+    commands: Receiver<VibrationCommand>,
+    writer: Arc<HidWriter>,
+    profile: Profile,
+    params: EngineParams,
+    current_intensity: Arc<AtomicU8>,
+) -> VibrationWorker {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_requested = Arc::clone(&stop);
+    let waker = rx.waker();
+    let thread = thread::spawn(move || {
+        let mut vib_manager = VibrationManager::new(writer, profile, params, current_intensity);
+
+        // When the engine has to be updated next; `None` while it's idle.
+        let mut next_update = Some(Instant::now());
+        while !stop_requested.load(Ordering::Relaxed) {
+            rx.wait(next_update);
+
+            // Pull in everything that arrived (non-blocking).
+            let mut changed = false;
+            let samples = loop {
+                match rx.try_recv() {
+                    // For each new sample, spawn a wave.
+                    Ok(sample) => vib_manager.push_sample(&sample),
+                    Err(e) => break e,
+                }
+                changed = true;
+            };
+
+            let command_error = loop {
+                match commands.try_recv() {
+                    Ok(command) => vib_manager.handle_command(command),
+                    Err(e) => break e,
+                }
+                changed = true;
+            };
 
-        let mut vib_manager = VibrationManager::new(hid_wrapper);
+            if samples == TryRecvError::Disconnected || command_error == TryRecvError::Disconnected
+            {
+                plugin_debugln!("Vibration worker channel closed");
+                break;
+            }
 
-        loop {
-            // Pull in all available data from the channel (non-blocking).
-            while let Ok((ax, ay, az)) = rx.try_recv() {
-                // For each new triple, spawn a wave.
-                vib_manager.spawn_wave_for_input(ax, ay, az);
+            let now = Instant::now();
+            let due = match next_update {
+                Some(deadline) => now >= deadline,
+                None => changed,
+            };
+            if !due {
+                continue;
             }
 
             // Update waves & write to motor
             vib_manager.update();
+            next_update = vib_manager.needs_updates().then(|| {
+                let next = next_update.unwrap_or(now) + PROCESS_INTERVAL;
+                // Don't try to catch up after a stall.
+                next.max(now)
+            });
+        }
 
-            thread::sleep(PROCESS_INTERVAL);
+        vib_manager.stop_motor();
+        vib_manager.stop_recording();
+        let lost = rx.counters();
+        if lost.dropped > 0 || lost.stale > 0 {
+            plugin_debugln!(
+                "Vibration worker fell behind: {} samples dropped, {} stale",
+                lost.dropped,
+                lost.stale
+            );
         }
+        plugin_debugln!("Vibration worker stopped");
     });
+    VibrationWorker {
+        stop,
+        waker,
+        thread: Some(thread),
+    }
 }
@@ -3,6 +3,7 @@ import "./App.css";
 import {Container, Nav, Navbar} from "react-bootstrap";
 import VibrationProfile from "./components/VibrationProfile.tsx";
 import UrsaMinorInfo from "./components/UrsaMinorInfo.tsx";
+import PluginPanel from "./components/PluginPanel.tsx";
 
 function App() {
 
@@ -105,6 +106,7 @@ function App() {
           <div className="flex-grow-1"></div>
 
           <UrsaMinorInfo/>
+          <PluginPanel/>
         </div>
 
         {/* Main Content */}
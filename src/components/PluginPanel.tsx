@@ -0,0 +1,85 @@
+import {Badge, Button, Card, Form} from "react-bootstrap";
+import {ChangeEvent, useEffect, useState} from "react";
+import {invoke} from "@tauri-apps/api/core";
+
+// What the X-Plane plugin reports through `plugin_status`
+interface PluginStatus {
+  enabled: boolean;
+  gain: number;
+  profile: string;
+  profiles: string[];
+  patterns: string[];
+  intensity: number;
+}
+
+const PluginPanel = () => {
+  const [status, setStatus] = useState<PluginStatus | null>(null);
+  const [message, setMessage] = useState("");
+
+  async function getStatus() {
+    // The command returns the status as JSON, or why the plugin can't be reached
+    let res = await invoke("plugin_status", {}) as string;
+    try {
+      setStatus(JSON.parse(res));
+    } catch {
+      setStatus(null);
+    }
+  }
+
+  // Send the picked JSON file to a command, and show what it answered
+  function sendFile(command: string, argument: string) {
+    return async (event: ChangeEvent<HTMLInputElement>) => {
+      const file = event.target.files?.[0];
+      event.target.value = "";
+      if (!file) {
+        return;
+      }
+      let res = await invoke(command, {[argument]: await file.text()});
+      setMessage(`${file.name}: ${res}`);
+    };
+  }
+
+  useEffect(() => {
+    const interval = setInterval(() => {
+      getStatus();
+    }, 1000);
+    return () => clearInterval(interval);
+  }, []);
+  return (
+    <div className="p-3">
+      <Card className="p-3">
+        <Card.Body className="d-flex flex-column align-items-center">
+          <Card.Title>
+            <h2>X-Plane Plugin</h2>
+          </Card.Title>
+          <Card.Text className="border-top py-3">
+            <p>
+              Plugin:
+              {
+                status ?
+                  <Badge bg="success" style={{marginLeft: "12px"}}>Running</Badge> :
+                  <Badge bg="secondary" style={{marginLeft: "12px"}}>Not running</Badge>
+              }
+            </p>
+            {status && <small className="text-muted">
+              Profile: {status.profile} · Vibration {status.enabled ? "on" : "off"} ·
+              Motor: {Math.round(status.intensity / 2.55)}%
+            </small>}
+          </Card.Text>
+          <Form.Group className="mb-2" style={{width: "100%"}}>
+            <Form.Label>Preview pattern</Form.Label>
+            <Form.Control type="file" accept=".json" onChange={sendFile("preview_pattern", "pattern")}/>
+          </Form.Group>
+          <Form.Group className="mb-2" style={{width: "100%"}}>
+            <Form.Label>Send profile to X-Plane</Form.Label>
+            <Form.Control type="file" accept=".json" disabled={!status}
+                          onChange={sendFile("push_profile", "profile")}/>
+          </Form.Group>
+          {message.length > 0 && <small className="text-muted">{message}</small>}
+        </Card.Body>
+      </Card>
+    </div>
+  );
+};
+
+export default PluginPanel;